@@ -0,0 +1,346 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use rmcp::ErrorData as McpError;
+use serde::{Deserialize, Serialize};
+
+use super::{build_client, check_status, log_request, read_json, read_logged_json, request_error, ImageBackend, ImageRequest, ImageResult};
+use crate::config::{ChatUsage, GeminiConfig, HttpConfig, ModelArchitecture, ModelProvider, OpenRouterModel, PayloadLogging};
+use crate::error::{ImageError, UpstreamError};
+use crate::image_utils;
+
+/// 直连 Google Gemini API 的 generateContent 接口生成和编辑图像
+pub struct GeminiBackend {
+    base_url: String,
+    model: String,
+    supported_models: Vec<String>,
+    client: reqwest::Client,
+    log_payloads: PayloadLogging,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest {
+    contents: Vec<GeminiContent>,
+    generation_config: GenerationConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GeminiContent {
+    #[serde(default)]
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiPart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inline_data: Option<InlineData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InlineData {
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    temperature: f32,
+    max_output_tokens: u32,
+    response_modalities: Vec<&'static str>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    content: Option<GeminiContent>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+    #[serde(default)]
+    total_token_count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiModelsResponse {
+    #[serde(default)]
+    models: Vec<GeminiModel>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiModel {
+    name: String,
+    display_name: Option<String>,
+    description: Option<String>,
+    input_token_limit: Option<u32>,
+    output_token_limit: Option<u32>,
+}
+
+impl GeminiBackend {
+    pub fn new(config: &GeminiConfig, http: &HttpConfig, log_payloads: PayloadLogging) -> Result<Self> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        let mut api_key: reqwest::header::HeaderValue = config.api_key.parse()?;
+        api_key.set_sensitive(true);
+        headers.insert(reqwest::header::HeaderName::from_static("x-goog-api-key"), api_key);
+
+        let client = build_client(http, headers)?;
+
+        Ok(Self {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            model: config.model.clone(),
+            supported_models: config.supported_models.clone(),
+            client,
+            log_payloads,
+        })
+    }
+
+    /// 发送一次 generateContent 请求，并把 inlineData 图像转换为 data URL
+    async fn generate_content(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        let url = format!("{}/models/{}:generateContent", self.base_url, request.model);
+
+        let mut parts = vec![GeminiPart {
+            text: Some(request.prompt.clone()),
+            inline_data: None,
+        }];
+        for image in &request.images {
+            // Gemini 只接受内联图像数据，URL 输入无法直接传递
+            let (mime_type, data) = image_utils::split_data_url(image).ok_or_else(|| {
+                McpError::from(ImageError::InvalidImage(
+                    format!("Gemini 后端只支持 base64 或本地文件图像输入，无法使用: {}", image),
+                ))
+            })?;
+            parts.push(GeminiPart {
+                text: None,
+                inline_data: Some(InlineData {
+                    mime_type: mime_type.to_string(),
+                    data: data.to_string(),
+                }),
+            });
+        }
+
+        let request_body = GenerateContentRequest {
+            contents: vec![GeminiContent { parts }],
+            generation_config: GenerationConfig {
+                temperature: request.temperature,
+                max_output_tokens: request.max_tokens,
+                response_modalities: vec!["TEXT", "IMAGE"],
+            },
+        };
+
+        log_request("Gemini 请求", &request.model, &request_body, self.log_payloads);
+        let response = self.client.post(&url).json(&request_body).send().await
+            .map_err(request_error)?;
+
+        let response_data: GenerateContentResponse = read_logged_json(
+            check_status(response, "Gemini API").await?,
+            "Gemini 响应",
+            &request.model,
+            self.log_payloads,
+        ).await?;
+
+        if let Some(block_reason) = response_data.prompt_feedback.and_then(|f| f.block_reason) {
+            return Err(ImageError::ContentRejected(UpstreamError {
+                service: "Gemini API".to_string(),
+                status: None,
+                message: format!("请求被拒绝 ({})", block_reason),
+                retry_after_ms: None,
+                provider_error: true,
+                metadata: None,
+            }).into());
+        }
+
+        let candidate = response_data.candidates.into_iter().next().ok_or_else(|| {
+            McpError::from(ImageError::InvalidResponse {
+                message: "Gemini 响应中 'candidates' 数组为空".to_string(),
+                body: None,
+            })
+        })?;
+
+        let mut texts = Vec::new();
+        let mut images = Vec::new();
+        for part in candidate.content.map(|c| c.parts).unwrap_or_default() {
+            if let Some(text) = part.text {
+                texts.push(text);
+            }
+            if let Some(inline_data) = part.inline_data {
+                images.push(format!("data:{};base64,{}", inline_data.mime_type, inline_data.data));
+            }
+        }
+
+        let text = if texts.is_empty() {
+            match candidate.finish_reason {
+                Some(reason) => format!("无内容 (finishReason: {})", reason),
+                None => "无内容".to_string(),
+            }
+        } else {
+            texts.join("\n")
+        };
+
+        let usage = response_data.usage_metadata.map(|usage| ChatUsage {
+            prompt_tokens: usage.prompt_token_count,
+            completion_tokens: usage.candidates_token_count,
+            total_tokens: usage.total_token_count,
+        });
+
+        Ok(ImageResult { text, images, usage })
+    }
+}
+
+#[async_trait]
+impl ImageBackend for GeminiBackend {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.model
+    }
+
+    fn supported_models(&self) -> &[String] {
+        &self.supported_models
+    }
+
+    async fn generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        self.generate_content(request).await
+    }
+
+    async fn edit(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        self.generate_content(request).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<OpenRouterModel>, McpError> {
+        let url = format!("{}/models", self.base_url);
+
+        let response = self.client.get(&url).query(&[("pageSize", "1000")]).send().await
+            .map_err(request_error)?;
+
+        let models: GeminiModelsResponse = read_json(check_status(response, "Gemini API").await?, "Gemini 模型列表").await?;
+
+        Ok(models.models.into_iter().map(|model| {
+            let id = model.name.trim_start_matches("models/").to_string();
+            // Gemini 的模型列表不包含输出模态，按模型名称判断是否为图像模型
+            let output_modalities = if id.contains("image") {
+                vec!["text".to_string(), "image".to_string()]
+            } else {
+                vec!["text".to_string()]
+            };
+            OpenRouterModel {
+                name: model.display_name.unwrap_or_else(|| id.clone()),
+                id,
+                description: model.description,
+                context_length: model.input_token_limit,
+                pricing: None,
+                top_provider: Some(ModelProvider {
+                    max_completion_tokens: model.output_token_limit,
+                    is_moderated: None,
+                }),
+                architecture: Some(ModelArchitecture {
+                    modality: None,
+                    input_modalities: Some(vec!["text".to_string(), "image".to_string()]),
+                    output_modalities: Some(output_modalities),
+                }),
+            }
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::test_support::{http_config, request};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+
+    const MODEL: &str = "gemini-2.5-flash-image";
+
+    /// 在随机端口上启动只实现 generateContent 的 Gemini 桩服务器，返回 API 基础 URL
+    async fn spawn_stub(response: &'static str) -> String {
+        let app = Router::new().route(
+            "/v1beta/models/{action}",
+            post(move |Json(request): Json<Value>| async move {
+                // 把收到的提示词放进文本部分，便于检查请求格式
+                let prompt = request.pointer("/contents/0/parts/0/text").cloned().unwrap_or_default();
+                (
+                    [(axum::http::header::CONTENT_TYPE, "application/json")],
+                    response.replace("{prompt}", prompt.as_str().unwrap_or_default()),
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/v1beta", address)
+    }
+
+    fn backend(base_url: String) -> GeminiBackend {
+        let config = GeminiConfig {
+            api_key: "test-key".to_string(),
+            base_url,
+            model: MODEL.to_string(),
+            supported_models: vec![MODEL.to_string()],
+        };
+        GeminiBackend::new(&config, &http_config(), PayloadLogging::Off).unwrap()
+    }
+
+
+    #[tokio::test]
+    async fn generate_converts_inline_data_to_data_url() {
+        let base_url = spawn_stub(r#"{
+            "candidates": [{"content": {"parts": [{"text": "画好了: {prompt}"}, {"inlineData": {"mimeType": "image/png", "data": "iVBORw0KGgo="}}]}, "finishReason": "STOP"}],
+            "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 1290, "totalTokenCount": 1293}
+        }"#).await;
+
+        let result = backend(base_url).generate(&request(MODEL, "一只猫")).await.unwrap();
+        assert_eq!(result.text, "画好了: 一只猫");
+        assert_eq!(result.images, vec!["data:image/png;base64,iVBORw0KGgo=".to_string()]);
+        let usage = result.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (3, 1290, 1293));
+    }
+
+    #[tokio::test]
+    async fn undecodable_response_is_not_retryable() {
+        let base_url = spawn_stub("{oops").await;
+
+        let error = backend(base_url).generate(&request(MODEL, "一只猫")).await.unwrap_err();
+        let data = error.data.unwrap();
+        assert_eq!(data["kind"], json!("invalid_response"));
+        assert_eq!(data["retryable"], json!(false));
+        assert_eq!(data["body"], json!("{oops"));
+    }
+
+    #[tokio::test]
+    async fn blocked_prompt_is_rejected() {
+        let base_url = spawn_stub(r#"{"candidates": [], "promptFeedback": {"blockReason": "SAFETY"}}"#).await;
+
+        let error = backend(base_url).generate(&request(MODEL, "一只猫")).await.unwrap_err();
+        assert_eq!(error.data.unwrap()["kind"], json!("content_rejected"));
+    }
+}
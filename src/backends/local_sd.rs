@@ -0,0 +1,543 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::{Engine as _, engine::general_purpose};
+use rmcp::ErrorData as McpError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::{check_status, http_error, local_sd_mock, log_request, read_json, read_logged_json, request_error, ImageBackend, ImageRequest, ImageResult};
+use crate::config::{HttpConfig, LocalSdApi, LocalSdConfig, ModelArchitecture, OpenRouterModel, PayloadLogging};
+use crate::error::ImageError;
+use crate::image_utils;
+
+/// ComfyUI 任务的最长等待时间
+const COMFYUI_TIMEOUT: Duration = Duration::from_secs(600);
+/// 轮询 ComfyUI /history 的间隔
+const COMFYUI_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 通过本地 Stable Diffusion 服务（Automatic1111 或 ComfyUI）离线生成和编辑图像
+pub struct LocalSdBackend {
+    base_url: String,
+    api: LocalSdApi,
+    model: String,
+    supported_models: Vec<String>,
+    steps: u32,
+    width: u32,
+    height: u32,
+    denoising_strength: f32,
+    workflow: Option<Value>,
+    edit_workflow: Option<Value>,
+    client: reqwest::Client,
+    log_payloads: PayloadLogging,
+}
+
+#[derive(Debug, Deserialize)]
+struct A1111Response {
+    #[serde(default)]
+    images: Vec<String>,
+    info: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct A1111Model {
+    title: String,
+    model_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyUploadResponse {
+    name: String,
+    #[serde(default)]
+    subfolder: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyPromptResponse {
+    prompt_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyHistoryEntry {
+    #[serde(default)]
+    outputs: HashMap<String, ComfyNodeOutput>,
+    status: Option<ComfyStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyNodeOutput {
+    #[serde(default)]
+    images: Vec<ComfyImageRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyImageRef {
+    filename: String,
+    #[serde(default)]
+    subfolder: String,
+    #[serde(rename = "type", default)]
+    folder_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyStatus {
+    status_str: Option<String>,
+}
+
+impl LocalSdBackend {
+    /// 创建本地后端；`base_url` 为 `mock` 时先启动内置的模拟服务器
+    pub async fn new(config: &LocalSdConfig, http: &HttpConfig, log_payloads: PayloadLogging) -> Result<Self> {
+        let base_url = if config.base_url == "mock" {
+            let mock_url = local_sd_mock::spawn().await?;
+            tracing::info!("本地 Stable Diffusion 模拟服务器已启动: {}", mock_url);
+            mock_url
+        } else {
+            config.base_url.trim_end_matches('/').to_string()
+        };
+
+        Ok(Self {
+            base_url,
+            api: config.api,
+            model: config.model.clone(),
+            supported_models: config.supported_models.clone(),
+            steps: config.steps,
+            width: config.width,
+            height: config.height,
+            denoising_strength: config.denoising_strength,
+            workflow: Self::load_workflow(config.workflow_path.as_deref())?,
+            edit_workflow: Self::load_workflow(config.edit_workflow_path.as_deref())?,
+            // 本地服务不经过代理，只使用超时设置
+            client: reqwest::Client::builder()
+                .no_proxy()
+                .connect_timeout(http.connect_timeout)
+                .timeout(http.request_timeout)
+                .build()?,
+            log_payloads,
+        })
+    }
+
+    fn load_workflow(path: Option<&str>) -> Result<Option<Value>> {
+        let Some(path) = path else {
+            return Ok(None);
+        };
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("读取 ComfyUI 工作流 {} 失败: {}", path, e))?;
+        let workflow = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("解析 ComfyUI 工作流 {} 失败: {}", path, e))?;
+        Ok(Some(workflow))
+    }
+
+    /// 输入图像必须是 data URL，返回 (MIME 类型, 纯 base64 数据)
+    fn inline_images(request: &ImageRequest) -> Result<Vec<(&str, &str)>, McpError> {
+        request.images.iter().map(|image| {
+            image_utils::split_data_url(image).ok_or_else(|| {
+                McpError::from(ImageError::InvalidImage(
+                    format!("本地后端只支持 base64 或本地文件图像输入，无法使用: {}", image),
+                ))
+            })
+        }).collect()
+    }
+
+    /// 每次请求使用不同的随机种子
+    fn random_seed() -> u64 {
+        let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        nanos.unsigned_abs() % 1_000_000_000
+    }
+
+    /// 调用 Automatic1111 的 txt2img 或 img2img 接口
+    async fn a1111_generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        let init_images = Self::inline_images(request)?;
+
+        let mut request_body = json!({
+            "prompt": request.prompt,
+            "negative_prompt": "",
+            "steps": self.steps,
+            "width": self.width,
+            "height": self.height,
+            "seed": -1
+        });
+        // "default" 表示使用 WebUI 当前加载的模型
+        if request.model != "default" {
+            request_body["override_settings"] = json!({ "sd_model_checkpoint": request.model });
+        }
+
+        let endpoint = if init_images.is_empty() {
+            "txt2img"
+        } else {
+            request_body["init_images"] = json!(init_images.iter().map(|(_, data)| *data).collect::<Vec<_>>());
+            request_body["denoising_strength"] = json!(self.denoising_strength);
+            "img2img"
+        };
+
+        let url = format!("{}/sdapi/v1/{}", self.base_url, endpoint);
+        log_request("Automatic1111 请求", &request.model, &request_body, self.log_payloads);
+        let response = self.client.post(&url).json(&request_body).send().await
+            .map_err(request_error)?;
+        let response_data: A1111Response = read_logged_json(
+            check_status(response, "本地 Stable Diffusion").await?,
+            "Automatic1111 响应",
+            &request.model,
+            self.log_payloads,
+        ).await?;
+
+        // info 是 JSON 字符串，只提取种子用于复现
+        let seed = response_data.info
+            .and_then(|info| serde_json::from_str::<Value>(&info).ok())
+            .and_then(|info| info.get("seed").and_then(Value::as_i64));
+        let text = match seed {
+            Some(seed) => format!("Automatic1111 {} 完成 (seed: {})", endpoint, seed),
+            None => format!("Automatic1111 {} 完成", endpoint),
+        };
+
+        let images = response_data.images.into_iter()
+            .map(|data| format!("data:image/png;base64,{}", data))
+            .collect();
+
+        Ok(ImageResult { text, images, usage: None })
+    }
+
+    /// 把工作流中值恰好为占位符的字符串替换为实际参数
+    fn fill_workflow(value: &mut Value, placeholders: &HashMap<&str, Value>) {
+        match value {
+            Value::String(s) => {
+                if let Some(replacement) = placeholders.get(s.as_str()) {
+                    *value = replacement.clone();
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    Self::fill_workflow(item, placeholders);
+                }
+            }
+            Value::Object(map) => {
+                for item in map.values_mut() {
+                    Self::fill_workflow(item, placeholders);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 内置的最小文生图 / 图生图工作流（API 格式）
+    fn default_workflow(is_edit: bool) -> Value {
+        let mut workflow = json!({
+            "4": { "class_type": "CheckpointLoaderSimple", "inputs": { "ckpt_name": "{{model}}" } },
+            "6": { "class_type": "CLIPTextEncode", "inputs": { "text": "{{prompt}}", "clip": ["4", 1] } },
+            "7": { "class_type": "CLIPTextEncode", "inputs": { "text": "", "clip": ["4", 1] } },
+            "5": { "class_type": "EmptyLatentImage", "inputs": { "width": "{{width}}", "height": "{{height}}", "batch_size": 1 } },
+            "3": { "class_type": "KSampler", "inputs": {
+                "seed": "{{seed}}", "steps": "{{steps}}", "cfg": 7, "sampler_name": "euler", "scheduler": "normal",
+                "denoise": 1, "model": ["4", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["5", 0]
+            } },
+            "8": { "class_type": "VAEDecode", "inputs": { "samples": ["3", 0], "vae": ["4", 2] } },
+            "9": { "class_type": "SaveImage", "inputs": { "filename_prefix": "nano-banana-mcp", "images": ["8", 0] } }
+        });
+        if is_edit {
+            workflow["10"] = json!({ "class_type": "LoadImage", "inputs": { "image": "{{image}}" } });
+            workflow["11"] = json!({ "class_type": "VAEEncode", "inputs": { "pixels": ["10", 0], "vae": ["4", 2] } });
+            workflow["3"]["inputs"]["latent_image"] = json!(["11", 0]);
+            workflow["3"]["inputs"]["denoise"] = json!("{{denoise}}");
+            if let Some(nodes) = workflow.as_object_mut() {
+                nodes.remove("5");
+            }
+        }
+        workflow
+    }
+
+    /// 上传输入图像到 ComfyUI，返回 LoadImage 节点可用的文件名
+    async fn comfy_upload(&self, mime_type: &str, data: &str) -> Result<String, McpError> {
+        let bytes = general_purpose::STANDARD.decode(data)
+            .map_err(|e| McpError::from(ImageError::InvalidImage(format!("base64解码失败: {}", e))))?;
+        let extension = mime_type.trim_start_matches("image/").split('+').next().unwrap_or("png");
+        let part = reqwest::multipart::Part::bytes(bytes)
+            .file_name(format!("nano_banana_input.{}", extension))
+            .mime_str(mime_type)
+            .map_err(|e| McpError::from(ImageError::InvalidImage(format!("无效的图像MIME类型: {}", e))))?;
+        let form = reqwest::multipart::Form::new()
+            .part("image", part)
+            .text("overwrite", "false");
+
+        let url = format!("{}/upload/image", self.base_url);
+        let response = self.client.post(&url).multipart(form).send().await
+            .map_err(|e| http_error("上传图像失败", e))?;
+        let uploaded: ComfyUploadResponse = read_json(
+            check_status(response, "本地 Stable Diffusion").await?,
+            "ComfyUI 上传响应",
+        ).await?;
+
+        Ok(if uploaded.subfolder.is_empty() {
+            uploaded.name
+        } else {
+            format!("{}/{}", uploaded.subfolder, uploaded.name)
+        })
+    }
+
+    /// 提交 ComfyUI 工作流，等待完成后下载所有输出图像
+    async fn comfy_generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        let inputs = Self::inline_images(request)?;
+        if inputs.len() > 1 {
+            return Err(ImageError::InvalidParams(
+                format!("ComfyUI 工作流只支持一张输入图像，当前提供了 {} 张", inputs.len()),
+            ).into());
+        }
+        let is_edit = !inputs.is_empty();
+
+        let mut workflow = if is_edit {
+            self.edit_workflow.clone()
+        } else {
+            self.workflow.clone()
+        }.unwrap_or_else(|| Self::default_workflow(is_edit));
+
+        // "default" 表示使用服务器上的第一个 checkpoint
+        let model = if request.model == "default" {
+            self.list_models().await?.into_iter().next()
+                .map(|m| m.id)
+                .ok_or_else(|| McpError::from(ImageError::Internal("ComfyUI 上没有可用的 checkpoint".to_string())))?
+        } else {
+            request.model.clone()
+        };
+
+        let mut placeholders: HashMap<&str, Value> = HashMap::from([
+            ("{{prompt}}", json!(request.prompt)),
+            ("{{model}}", json!(model)),
+            ("{{seed}}", json!(Self::random_seed())),
+            ("{{steps}}", json!(self.steps)),
+            ("{{width}}", json!(self.width)),
+            ("{{height}}", json!(self.height)),
+            ("{{denoise}}", json!(self.denoising_strength)),
+        ]);
+        if let Some((mime_type, data)) = inputs.first() {
+            placeholders.insert("{{image}}", json!(self.comfy_upload(mime_type, data).await?));
+        }
+        Self::fill_workflow(&mut workflow, &placeholders);
+
+        let url = format!("{}/prompt", self.base_url);
+        let request_body = json!({ "prompt": workflow, "client_id": "nano-banana-mcp" });
+        log_request("ComfyUI 请求", &request.model, &request_body, self.log_payloads);
+        let response = self.client.post(&url).json(&request_body).send().await
+            .map_err(request_error)?;
+        let prompt_id = read_logged_json::<ComfyPromptResponse>(
+            check_status(response, "本地 Stable Diffusion").await?,
+            "ComfyUI 响应",
+            &request.model,
+            self.log_payloads,
+        ).await?.prompt_id;
+
+        let entry = self.comfy_wait(&prompt_id).await?;
+
+        let mut images = Vec::new();
+        for image in entry.outputs.values().flat_map(|output| &output.images) {
+            // 临时预览图不是工作流的最终输出
+            if image.folder_type == "temp" {
+                continue;
+            }
+            images.push(self.comfy_download(image).await?);
+        }
+
+        Ok(ImageResult {
+            text: format!("ComfyUI 工作流完成 (prompt_id: {})", prompt_id),
+            images,
+            usage: None,
+        })
+    }
+
+    /// 轮询 /history 直到任务完成或超时
+    async fn comfy_wait(&self, prompt_id: &str) -> Result<ComfyHistoryEntry, McpError> {
+        let url = format!("{}/history/{}", self.base_url, prompt_id);
+        let started = std::time::Instant::now();
+
+        loop {
+            let response = self.client.get(&url).send().await
+                .map_err(request_error)?;
+            let mut history: HashMap<String, ComfyHistoryEntry> = read_json(
+                check_status(response, "本地 Stable Diffusion").await?,
+                "ComfyUI 历史记录",
+            ).await?;
+
+            if let Some(entry) = history.remove(prompt_id) {
+                let status = entry.status.as_ref().and_then(|s| s.status_str.as_deref());
+                if status == Some("error") {
+                    return Err(ImageError::Internal(
+                        format!("ComfyUI 工作流执行失败 (prompt_id: {})", prompt_id),
+                    ).into());
+                }
+                return Ok(entry);
+            }
+
+            if started.elapsed() > COMFYUI_TIMEOUT {
+                return Err(ImageError::Network {
+                    message: format!("等待 ComfyUI 工作流超时 (prompt_id: {})", prompt_id),
+                    timeout: Some("request"),
+                    retryable: false,
+                }.into());
+            }
+            tokio::time::sleep(COMFYUI_POLL_INTERVAL).await;
+        }
+    }
+
+    /// 通过 /view 下载输出图像并转换为 data URL
+    async fn comfy_download(&self, image: &ComfyImageRef) -> Result<String, McpError> {
+        let url = format!("{}/view", self.base_url);
+        let response = self.client.get(&url)
+            .query(&[
+                ("filename", image.filename.as_str()),
+                ("subfolder", image.subfolder.as_str()),
+                ("type", image.folder_type.as_str()),
+            ])
+            .send().await
+            .map_err(|e| http_error("下载图像失败", e))?;
+        let response = check_status(response, "本地 Stable Diffusion").await?;
+
+        let mime_type = response.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| v.starts_with("image/"))
+            .unwrap_or("image/png")
+            .to_string();
+        let bytes = response.bytes().await
+            .map_err(|e| http_error("下载图像失败", e))?;
+
+        Ok(format!("data:{};base64,{}", mime_type, general_purpose::STANDARD.encode(&bytes)))
+    }
+}
+
+#[async_trait]
+impl ImageBackend for LocalSdBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.model
+    }
+
+    fn supported_models(&self) -> &[String] {
+        &self.supported_models
+    }
+
+    async fn generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        match self.api {
+            LocalSdApi::Automatic1111 => self.a1111_generate(request).await,
+            LocalSdApi::ComfyUi => self.comfy_generate(request).await,
+        }
+    }
+
+    async fn edit(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        self.generate(request).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<OpenRouterModel>, McpError> {
+        let models: Vec<(String, String)> = match self.api {
+            LocalSdApi::Automatic1111 => {
+                let url = format!("{}/sdapi/v1/sd-models", self.base_url);
+                let response = self.client.get(&url).send().await
+                    .map_err(request_error)?;
+                read_json::<Vec<A1111Model>>(check_status(response, "本地 Stable Diffusion").await?, "模型列表").await?
+                    .into_iter()
+                    .map(|model| (model.model_name, model.title))
+                    .collect()
+            }
+            LocalSdApi::ComfyUi => {
+                let url = format!("{}/object_info/CheckpointLoaderSimple", self.base_url);
+                let response = self.client.get(&url).send().await
+                    .map_err(request_error)?;
+                let object_info: Value = read_json(check_status(response, "本地 Stable Diffusion").await?, "模型列表").await?;
+                // ckpt_name 的第一个元素是可选 checkpoint 列表
+                object_info.pointer("/CheckpointLoaderSimple/input/required/ckpt_name/0")
+                    .and_then(Value::as_array)
+                    .map(|names| names.iter()
+                        .filter_map(Value::as_str)
+                        .map(|name| (name.to_string(), name.to_string()))
+                        .collect())
+                    .unwrap_or_default()
+            }
+        };
+
+        Ok(models.into_iter().map(|(id, name)| OpenRouterModel {
+            id,
+            name,
+            description: None,
+            context_length: None,
+            pricing: None,
+            top_provider: None,
+            architecture: Some(ModelArchitecture {
+                modality: Some("text+image->image".to_string()),
+                input_modalities: Some(vec!["text".to_string(), "image".to_string()]),
+                output_modalities: Some(vec!["image".to_string()]),
+            }),
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::test_support::{http_config, request};
+
+    async fn mock_backend(api: LocalSdApi) -> LocalSdBackend {
+        let config = LocalSdConfig {
+            base_url: "mock".to_string(),
+            api,
+            model: "default".to_string(),
+            supported_models: vec!["default".to_string()],
+            steps: 20,
+            width: 512,
+            height: 512,
+            denoising_strength: 0.75,
+            workflow_path: None,
+            edit_workflow_path: None,
+        };
+        LocalSdBackend::new(&config, &http_config(), PayloadLogging::Off).await.unwrap()
+    }
+
+
+    fn decode_png(data_url: &str) -> image::DynamicImage {
+        let (mime_type, data) = image_utils::split_data_url(data_url).unwrap();
+        assert_eq!(mime_type, "image/png");
+        image::load_from_memory(&general_purpose::STANDARD.decode(data).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn automatic1111_mock_is_deterministic_per_prompt() {
+        let backend = mock_backend(LocalSdApi::Automatic1111).await;
+
+        let first = backend.generate(&request("default", "一只猫")).await.unwrap();
+        let second = backend.generate(&request("default", "一只猫")).await.unwrap();
+        let other = backend.generate(&request("default", "一只狗")).await.unwrap();
+
+        assert_eq!(first.text, "Automatic1111 txt2img 完成 (seed: 42)");
+        assert_eq!(first.images.len(), 1);
+        assert_eq!(first.images, second.images);
+        assert_ne!(first.images, other.images);
+        assert_eq!(decode_png(&first.images[0]).width(), 64);
+    }
+
+    #[tokio::test]
+    async fn automatic1111_mock_edits_with_input_image() {
+        let backend = mock_backend(LocalSdApi::Automatic1111).await;
+        let input = backend.generate(&request("default", "一只猫")).await.unwrap().images.remove(0);
+
+        let result = backend.edit(&ImageRequest { images: vec![input.clone()], ..request("default", "戴帽子") }).await.unwrap();
+        assert!(result.text.contains("img2img"));
+        assert_eq!(result.images, vec![input]);
+    }
+
+    #[tokio::test]
+    async fn comfyui_mock_runs_workflow() {
+        let backend = mock_backend(LocalSdApi::ComfyUi).await;
+
+        let result = backend.generate(&request("default", "一只猫")).await.unwrap();
+        assert!(result.text.starts_with("ComfyUI 工作流完成"));
+        assert_eq!(result.images.len(), 1);
+        assert_eq!(decode_png(&result.images[0]).width(), 64);
+    }
+
+    #[tokio::test]
+    async fn mock_lists_checkpoints() {
+        for api in [LocalSdApi::Automatic1111, LocalSdApi::ComfyUi] {
+            let models = mock_backend(api).await.list_models().await.unwrap();
+            assert!(models.iter().any(|model| model.id.starts_with("mock-sd-v1")), "{:?}", api);
+        }
+    }
+}
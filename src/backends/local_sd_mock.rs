@@ -0,0 +1,126 @@
+use anyhow::Result;
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::{Engine as _, engine::general_purpose};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::image_utils;
+
+/// 模拟服务器返回的占位图像边长
+const MOCK_IMAGE_SIZE: u32 = 64;
+const MOCK_CHECKPOINT: &str = "mock-sd-v1.safetensors";
+
+#[derive(Default)]
+struct MockState {
+    next_id: AtomicU64,
+    /// ComfyUI 输出文件名 -> PNG 字节
+    outputs: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+/// 在随机端口上启动内置的模拟服务器，返回其基础 URL
+///
+/// 服务器同时模拟 Automatic1111 和 ComfyUI 的接口并返回占位图像，
+/// 用于在没有 GPU 的环境中测试 `local` 后端
+pub async fn spawn() -> Result<String> {
+    let app = Router::new()
+        .route("/sdapi/v1/txt2img", post(a1111_txt2img))
+        .route("/sdapi/v1/img2img", post(a1111_img2img))
+        .route("/sdapi/v1/sd-models", get(a1111_models))
+        .route("/upload/image", post(comfy_upload))
+        .route("/prompt", post(comfy_prompt))
+        .route("/history/{prompt_id}", get(comfy_history))
+        .route("/view", get(comfy_view))
+        .route("/object_info/CheckpointLoaderSimple", get(comfy_checkpoints))
+        .with_state(Arc::new(MockState::default()));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("本地 Stable Diffusion 模拟服务器退出: {}", e);
+        }
+    });
+
+    Ok(format!("http://{}", address))
+}
+
+/// 根据输入文本生成占位 PNG，相同输入得到相同图像
+fn placeholder_png(seed_text: &str) -> Vec<u8> {
+    image_utils::placeholder_png(image_utils::stable_hash(seed_text.as_bytes()), MOCK_IMAGE_SIZE)
+}
+
+async fn a1111_txt2img(Json(request): Json<Value>) -> Json<Value> {
+    let prompt = request.get("prompt").and_then(Value::as_str).unwrap_or_default();
+    Json(json!({
+        "images": [general_purpose::STANDARD.encode(placeholder_png(prompt))],
+        "info": json!({ "seed": 42, "prompt": prompt }).to_string()
+    }))
+}
+
+/// 图生图直接返回第一张输入图像
+async fn a1111_img2img(Json(request): Json<Value>) -> Json<Value> {
+    let prompt = request.get("prompt").and_then(Value::as_str).unwrap_or_default();
+    let image = request.pointer("/init_images/0")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| general_purpose::STANDARD.encode(placeholder_png(prompt)));
+    Json(json!({
+        "images": [image],
+        "info": json!({ "seed": 42, "prompt": prompt }).to_string()
+    }))
+}
+
+async fn a1111_models() -> Json<Value> {
+    Json(json!([{
+        "title": format!("{} [0000000000]", MOCK_CHECKPOINT),
+        "model_name": MOCK_CHECKPOINT.trim_end_matches(".safetensors")
+    }]))
+}
+
+async fn comfy_upload() -> Json<Value> {
+    Json(json!({ "name": "mock_input.png", "subfolder": "", "type": "input" }))
+}
+
+async fn comfy_prompt(State(state): State<Arc<MockState>>, Json(request): Json<Value>) -> Json<Value> {
+    let id = state.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let workflow = request.get("prompt").map(Value::to_string).unwrap_or_default();
+    state.outputs.lock().unwrap().insert(format!("mock_{}.png", id), placeholder_png(&workflow));
+    Json(json!({ "prompt_id": id.to_string(), "number": id, "node_errors": {} }))
+}
+
+async fn comfy_history(State(state): State<Arc<MockState>>, Path(prompt_id): Path<String>) -> Json<Value> {
+    let filename = format!("mock_{}.png", prompt_id);
+    if !state.outputs.lock().unwrap().contains_key(&filename) {
+        return Json(json!({}));
+    }
+    Json(json!({
+        prompt_id: {
+            "outputs": {
+                "9": { "images": [{ "filename": filename, "subfolder": "", "type": "output" }] }
+            },
+            "status": { "status_str": "success", "completed": true }
+        }
+    }))
+}
+
+async fn comfy_view(State(state): State<Arc<MockState>>, Query(query): Query<HashMap<String, String>>) -> impl IntoResponse {
+    let filename = query.get("filename").cloned().unwrap_or_default();
+    match state.outputs.lock().unwrap().get(&filename) {
+        Some(png) => ([(header::CONTENT_TYPE, "image/png")], png.clone()).into_response(),
+        None => axum::http::StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn comfy_checkpoints() -> Json<Value> {
+    Json(json!({
+        "CheckpointLoaderSimple": {
+            "input": { "required": { "ckpt_name": [[MOCK_CHECKPOINT]] } }
+        }
+    }))
+}
@@ -0,0 +1,147 @@
+use async_trait::async_trait;
+use base64::{Engine as _, engine::general_purpose};
+use rmcp::ErrorData as McpError;
+
+use super::{ImageBackend, ImageRequest, ImageResult};
+use crate::config::{ChatUsage, ModelArchitecture, OpenRouterModel};
+use crate::image_utils;
+
+/// mock 后端允许的模型列表，第一个为默认模型
+pub const MOCK_MODELS: &[&str] = &["mock/image-1", "mock/image-2"];
+
+/// 占位图像边长
+const MOCK_IMAGE_SIZE: u32 = 128;
+/// 每张生成图像计入的 completion tokens，与 Gemini 图像输出的计费方式一致
+const MOCK_IMAGE_TOKENS: u32 = 1290;
+
+/// 不访问任何外部服务的确定性后端：相同的模型、提示词和输入图像总是得到相同的占位 PNG，
+/// 用于离线开发和 CI 测试完整的工具 → 保存 → 响应流程
+pub struct MockBackend {
+    supported_models: Vec<String>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self {
+            supported_models: MOCK_MODELS.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    fn render(&self, request: &ImageRequest) -> ImageResult {
+        let mut seed = format!("{}\n{}", request.model, request.prompt);
+        for image in &request.images {
+            seed.push('\n');
+            seed.push_str(image);
+        }
+        let hash = image_utils::stable_hash(seed.as_bytes());
+        let png = image_utils::placeholder_png(hash, MOCK_IMAGE_SIZE);
+
+        // 粗略按 4 个字符 1 个 token 估算提示词用量，每张输入图像另计一张图像的用量
+        let prompt_tokens = (request.prompt.chars().count() as u32).div_ceil(4)
+            + request.images.len() as u32 * MOCK_IMAGE_TOKENS;
+        let usage = ChatUsage {
+            prompt_tokens,
+            completion_tokens: MOCK_IMAGE_TOKENS,
+            total_tokens: prompt_tokens + MOCK_IMAGE_TOKENS,
+        };
+
+        ImageResult {
+            text: format!("模拟图像 (hash: {:016x})", hash),
+            images: vec![format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(png))],
+            usage: Some(usage),
+        }
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ImageBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn default_model(&self) -> &str {
+        MOCK_MODELS[0]
+    }
+
+    fn supported_models(&self) -> &[String] {
+        &self.supported_models
+    }
+
+    async fn generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        Ok(self.render(request))
+    }
+
+    async fn edit(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        Ok(self.render(request))
+    }
+
+    async fn list_models(&self) -> Result<Vec<OpenRouterModel>, McpError> {
+        Ok(self.supported_models.iter().map(|id| OpenRouterModel {
+            id: id.clone(),
+            name: format!("Mock {}", id.trim_start_matches("mock/")),
+            description: Some("确定性占位图像，不访问外部服务".to_string()),
+            context_length: None,
+            pricing: None,
+            top_provider: None,
+            architecture: Some(ModelArchitecture {
+                modality: Some("text+image->text+image".to_string()),
+                input_modalities: Some(vec!["text".to_string(), "image".to_string()]),
+                output_modalities: Some(vec!["text".to_string(), "image".to_string()]),
+            }),
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::test_support::request;
+
+
+    #[tokio::test]
+    async fn identical_requests_produce_identical_images() {
+        let backend = MockBackend::new();
+
+        let first = backend.generate(&request(MOCK_MODELS[0], "一只猫")).await.unwrap();
+        let second = MockBackend::new().generate(&request(MOCK_MODELS[0], "一只猫")).await.unwrap();
+
+        assert_eq!(first.text, second.text);
+        assert_eq!(first.images, second.images);
+        assert_eq!(first.images.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prompt_model_and_inputs_change_the_image() {
+        let backend = MockBackend::new();
+        let base = backend.generate(&request(MOCK_MODELS[0], "一只猫")).await.unwrap();
+
+        let other_prompt = backend.generate(&request(MOCK_MODELS[0], "一只狗")).await.unwrap();
+        let other_model = backend.generate(&request(MOCK_MODELS[1], "一只猫")).await.unwrap();
+        let with_input = backend.edit(&ImageRequest {
+            images: vec![base.images[0].clone()],
+            ..request(MOCK_MODELS[0], "一只猫")
+        }).await.unwrap();
+
+        for other in [other_prompt, other_model, with_input] {
+            assert_ne!(base.images, other.images);
+        }
+    }
+
+    #[tokio::test]
+    async fn output_is_a_decodable_png() {
+        let result = MockBackend::new().generate(&request(MOCK_MODELS[0], "一只猫")).await.unwrap();
+        let data = result.images[0].strip_prefix("data:image/png;base64,").unwrap();
+        let image = image::load_from_memory(&general_purpose::STANDARD.decode(data).unwrap()).unwrap();
+
+        assert_eq!((image.width(), image.height()), (MOCK_IMAGE_SIZE, MOCK_IMAGE_SIZE));
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.completion_tokens, MOCK_IMAGE_TOKENS);
+        assert_eq!(usage.total_tokens, usage.prompt_tokens + MOCK_IMAGE_TOKENS);
+    }
+}
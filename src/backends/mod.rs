@@ -0,0 +1,285 @@
+pub mod gemini;
+pub mod local_sd;
+mod local_sd_mock;
+pub mod mock;
+pub mod openai_images;
+pub mod openrouter;
+pub mod openrouter_client;
+pub mod retry;
+
+use async_trait::async_trait;
+use rmcp::ErrorData as McpError;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::{ChatUsage, HttpConfig, OpenRouterModel, PayloadLogging};
+use crate::error::{ImageError, UpstreamError};
+use crate::redact;
+
+/// 错误数据中保留的响应片段长度
+const BODY_SNIPPET_CHARS: usize = 500;
+
+/// 发送给后端的图像生成/编辑请求
+#[derive(Debug, Clone)]
+pub struct ImageRequest {
+    pub model: String,
+    pub prompt: String,
+    /// 已解析的输入图像：data URL 或 http(s) URL
+    pub images: Vec<String>,
+    pub max_tokens: u32,
+    pub temperature: f32,
+}
+
+/// 后端返回的生成结果
+#[derive(Debug, Default)]
+pub struct ImageResult {
+    /// 模型返回的文本内容
+    pub text: String,
+    /// 生成的图像：data URL 或 http(s) URL
+    pub images: Vec<String>,
+    pub usage: Option<ChatUsage>,
+}
+
+/// 图像生成后端，MCP 工具层只通过该 trait 访问上游服务
+#[async_trait]
+pub trait ImageBackend: Send + Sync {
+    /// 后端名称，用于 `--backend` 和工具的 `backend` 参数
+    fn name(&self) -> &'static str;
+
+    /// 未指定模型时使用的默认模型
+    fn default_model(&self) -> &str;
+
+    /// 允许使用的模型列表
+    fn supported_models(&self) -> &[String];
+
+    /// 主模型失败时按顺序尝试的回退模型
+    fn fallback_models(&self) -> &[String] {
+        &[]
+    }
+
+    /// 根据文本提示词生成图像
+    async fn generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError>;
+
+    /// 根据指令编辑输入图像
+    async fn edit(&self, request: &ImageRequest) -> Result<ImageResult, McpError>;
+
+    /// 列出后端提供的模型
+    async fn list_models(&self) -> Result<Vec<OpenRouterModel>, McpError>;
+
+    /// 校验模型是否在允许列表中
+    fn validate_model(&self, model: &str) -> Result<(), McpError> {
+        if self.supported_models().iter().any(|m| m == model) {
+            Ok(())
+        } else {
+            Err(ImageError::UnsupportedModel {
+                backend: self.name().to_string(),
+                model: model.to_string(),
+                supported: self.supported_models().to_vec(),
+            }.into())
+        }
+    }
+}
+
+/// 按 HTTP 配置创建客户端：连接超时、总超时和代理
+pub(crate) fn build_client(http: &HttpConfig, headers: reqwest::header::HeaderMap) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .default_headers(headers)
+        .connect_timeout(http.connect_timeout)
+        .timeout(http.request_timeout);
+
+    // 未显式配置代理时 reqwest 会使用 HTTP_PROXY / HTTPS_PROXY / ALL_PROXY 环境变量
+    if let Some(proxy) = &http.proxy {
+        let proxy = reqwest::Proxy::all(proxy)
+            .map_err(|e| anyhow::anyhow!("无效的代理地址 {}: {}", proxy, e))?
+            .no_proxy(reqwest::NoProxy::from_env());
+        builder = builder.proxy(proxy);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// 把 reqwest 错误转换为错误，超时给出明确的提示；超时和连接失败可以重试
+pub(crate) fn http_error(context: &str, e: reqwest::Error) -> McpError {
+    // reqwest 的 Display 不包含底层原因（如连接被拒绝、代理握手失败），逐级追加
+    let mut detail = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(cause) = source {
+        detail.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+
+    let (message, timeout) = if e.is_connect() && e.is_timeout() {
+        (format!("{}: 连接上游服务超时（可通过 MCP_CONNECT_TIMEOUT_SECS 或 --connect-timeout 调整）: {}", context, detail), Some("connect"))
+    } else if e.is_timeout() {
+        (format!("{}: 请求超时，上游服务未在总超时时间内完成（可通过 MCP_REQUEST_TIMEOUT_SECS 或 --request-timeout 调整）: {}", context, detail), Some("request"))
+    } else {
+        (format!("{}: {}", context, detail), None)
+    };
+
+    ImageError::Network {
+        message,
+        timeout,
+        retryable: e.is_timeout() || e.is_connect(),
+    }.into()
+}
+
+/// 把请求发送失败转换为错误
+pub(crate) fn request_error(e: reqwest::Error) -> McpError {
+    http_error("请求失败", e)
+}
+
+/// 检查 HTTP 状态码，失败时把错误详情、是否可重试以及 Retry-After 放入错误数据
+pub(crate) async fn check_status(response: reqwest::Response, service: &str) -> Result<reqwest::Response, McpError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after_ms = response.headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(retry::parse_retry_after)
+        .map(|delay| delay.as_millis() as u64);
+    let error_text = response.text().await.unwrap_or_else(|_| "无法获取错误详情".to_string());
+    // 审核等错误的详情在 JSON 响应体的 error.metadata 中
+    let metadata = serde_json::from_str::<serde_json::Value>(&error_text).ok()
+        .and_then(|body| body.pointer("/error/metadata").cloned());
+
+    Err(ImageError::upstream(UpstreamError {
+        service: service.to_string(),
+        status: Some(status.as_u16()),
+        message: error_text,
+        retry_after_ms,
+        provider_error: false,
+        metadata,
+    }).into())
+}
+
+/// 读取响应体并反序列化，解析失败时返回不可重试的 `ImageError::InvalidResponse`
+///
+/// `what` 用于错误信息，例如 "Gemini 响应"
+pub(crate) async fn read_json<T: serde::de::DeserializeOwned>(response: reqwest::Response, what: &str) -> Result<T, McpError> {
+    let body = response.text().await
+        .map_err(|e| http_error(&format!("读取{}失败", what), e))?;
+    parse_body(&body, what)
+}
+
+/// 与 `read_json` 相同，同时按 `--log-payloads` 设置记录响应体
+pub(crate) async fn read_logged_json<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+    what: &str,
+    model: &str,
+    log_payloads: PayloadLogging,
+) -> Result<T, McpError> {
+    let status = response.status().as_u16();
+    let body = response.text().await
+        .map_err(|e| http_error(&format!("读取{}失败", what), e))?;
+    tracing::debug!(model, status, bytes = body.len(), "收到{}", what);
+    log_payload(what, model, &body, log_payloads);
+    parse_body(&body, what)
+}
+
+/// 按 `--log-payloads` 设置记录序列化后的请求体，关闭时不做序列化
+pub(crate) fn log_request<T: serde::Serialize + ?Sized>(what: &str, model: &str, request: &T, log_payloads: PayloadLogging) {
+    if log_payloads != PayloadLogging::Off {
+        log_payload(what, model, &serde_json::to_string(request).unwrap_or_default(), log_payloads);
+    }
+}
+
+fn log_payload(what: &str, model: &str, body: &str, log_payloads: PayloadLogging) {
+    if let Some(payload) = redact::payload(body, log_payloads) {
+        tracing::info!(model, %payload, "{}", what);
+    }
+}
+
+/// 反序列化响应体，失败时报告具体的字段和位置，并在错误数据中附带响应片段
+pub(crate) fn parse_body<T: serde::de::DeserializeOwned>(body: &str, what: &str) -> Result<T, McpError> {
+    serde_json::from_str(body).map_err(|e| {
+        let snippet: String = body.chars().take(BODY_SNIPPET_CHARS).collect();
+        ImageError::InvalidResponse {
+            message: format!("{}解析失败: {}", what, e),
+            body: Some(snippet),
+        }.into()
+    })
+}
+
+/// 按名称注册的后端集合，所有会话共享
+#[derive(Clone)]
+pub struct BackendRegistry {
+    backends: Arc<HashMap<&'static str, Arc<dyn ImageBackend>>>,
+    default_backend: &'static str,
+}
+
+impl BackendRegistry {
+    /// 使用已注册的后端创建集合，`default_backend` 必须是其中之一
+    pub fn new(backends: Vec<Arc<dyn ImageBackend>>, default_backend: &str) -> anyhow::Result<Self> {
+        let backends: HashMap<_, _> = backends
+            .into_iter()
+            .map(|backend| (backend.name(), backend))
+            .collect();
+
+        let default_backend = backends
+            .keys()
+            .copied()
+            .find(|name| *name == default_backend)
+            .ok_or_else(|| anyhow::anyhow!("后端 {} 不可用（未配置或不支持）。可用后端: {}",
+                default_backend,
+                Self::sorted_names(&backends).join(", ")))?;
+
+        Ok(Self {
+            backends: Arc::new(backends),
+            default_backend,
+        })
+    }
+
+    /// 按名称获取后端，未指定时返回默认后端
+    pub fn get(&self, name: Option<&str>) -> Result<Arc<dyn ImageBackend>, McpError> {
+        let name = name.unwrap_or(self.default_backend);
+        self.backends.get(name).cloned().ok_or_else(|| {
+            ImageError::UnknownBackend {
+                name: name.to_string(),
+                available: self.names().iter().map(|name| name.to_string()).collect(),
+            }.into()
+        })
+    }
+
+    /// 所有已注册后端的名称
+    pub fn names(&self) -> Vec<&'static str> {
+        Self::sorted_names(&self.backends)
+    }
+
+    fn sorted_names(backends: &HashMap<&'static str, Arc<dyn ImageBackend>>) -> Vec<&'static str> {
+        let mut names: Vec<_> = backends.keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// 各后端测试共用的配置和请求
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::time::Duration;
+
+    use super::ImageRequest;
+    use crate::config::HttpConfig;
+
+    /// 不使用代理、超时较短的 HTTP 配置
+    pub(crate) fn http_config() -> HttpConfig {
+        HttpConfig {
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(5),
+            proxy: None,
+        }
+    }
+
+    /// 不带输入图像、使用默认 max_tokens 和 temperature 的请求
+    pub(crate) fn request(model: &str, prompt: &str) -> ImageRequest {
+        ImageRequest {
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            images: Vec::new(),
+            max_tokens: 1000,
+            temperature: 0.7,
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::{Engine as _, engine::general_purpose};
+use rmcp::ErrorData as McpError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{build_client, check_status, log_request, read_json, read_logged_json, request_error, ImageBackend, ImageRequest, ImageResult};
+use crate::config::{ChatUsage, HttpConfig, ModelArchitecture, OpenAiImagesConfig, OpenRouterModel, PayloadLogging, UrlFetchConfig};
+use crate::error::ImageError;
+use crate::image_utils;
+use crate::url_fetch::UrlFetcher;
+
+/// 通过 OpenAI 兼容的 /images/generations 和 /images/edits 接口生成和编辑图像
+pub struct OpenAiImagesBackend {
+    base_url: String,
+    model: String,
+    supported_models: Vec<String>,
+    size: Option<String>,
+    client: reqwest::Client,
+    /// 下载 URL 形式的结果图像：不携带 API 密钥，限制大小并拒绝内网地址
+    url_fetcher: UrlFetcher,
+    log_payloads: PayloadLogging,
+}
+
+#[derive(Debug, Serialize)]
+struct GenerationRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    n: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<&'static str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImagesResponse {
+    #[serde(default)]
+    data: Vec<ImageData>,
+    output_format: Option<String>,
+    usage: Option<ImagesUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageData {
+    b64_json: Option<String>,
+    url: Option<String>,
+    revised_prompt: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImagesUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+    #[serde(default)]
+    total_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiModelsResponse {
+    #[serde(default)]
+    data: Vec<OpenAiModel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiModel {
+    id: String,
+}
+
+impl OpenAiImagesBackend {
+    pub fn new(config: &OpenAiImagesConfig, http: &HttpConfig, url_fetch: &UrlFetchConfig, log_payloads: PayloadLogging) -> Result<Self> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(api_key) = &config.api_key {
+            let mut value: reqwest::header::HeaderValue = format!("Bearer {}", api_key).parse()?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+
+        let client = build_client(http, headers)?;
+
+        Ok(Self {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            model: config.model.clone(),
+            supported_models: config.supported_models.clone(),
+            size: config.size.clone(),
+            client,
+            url_fetcher: UrlFetcher::for_results(url_fetch, http, &config.base_url),
+            log_payloads,
+        })
+    }
+
+    /// gpt-image 系列总是返回 b64_json，且不接受 response_format 参数
+    fn response_format(model: &str) -> Option<&'static str> {
+        if model.starts_with("gpt-image") {
+            None
+        } else {
+            Some("b64_json")
+        }
+    }
+
+    /// 检查状态码并把 images 响应转换为统一的生成结果
+    ///
+    /// URL 形式的结果会立即下载为 data URL（上游返回的 URL 通常很快过期），与 b64_json 走同一套保存流程
+    async fn parse_response(&self, response: reqwest::Response, model: &str) -> Result<ImageResult, McpError> {
+        let response_data: ImagesResponse = read_logged_json(
+            check_status(response, "Images API").await?,
+            "Images API 响应",
+            model,
+            self.log_payloads,
+        ).await?;
+
+        let mime_type = match response_data.output_format.as_deref() {
+            Some("jpeg") | Some("jpg") => "image/jpeg",
+            Some("webp") => "image/webp",
+            _ => "image/png",
+        };
+
+        let mut images = Vec::new();
+        let mut revised_prompts = Vec::new();
+        for image in response_data.data {
+            if let Some(b64_json) = image.b64_json {
+                images.push(format!("data:{};base64,{}", mime_type, b64_json));
+            } else if let Some(url) = image.url {
+                images.push(self.url_fetcher.download(&url).await?);
+            }
+            if let Some(revised_prompt) = image.revised_prompt {
+                revised_prompts.push(revised_prompt);
+            }
+        }
+
+        let text = if revised_prompts.is_empty() {
+            "无内容".to_string()
+        } else {
+            format!("修订后的提示词: {}", revised_prompts.join("\n"))
+        };
+
+        let usage = response_data.usage.map(|usage| ChatUsage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+        });
+
+        Ok(ImageResult { text, images, usage })
+    }
+}
+
+#[async_trait]
+impl ImageBackend for OpenAiImagesBackend {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.model
+    }
+
+    fn supported_models(&self) -> &[String] {
+        &self.supported_models
+    }
+
+    async fn generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        // /images/generations 不接受输入图像，静默丢弃会把编辑变成纯文生图
+        if !request.images.is_empty() {
+            return Err(ImageError::InvalidParams(
+                "Images API 的生成接口不支持输入图像，请使用编辑操作".to_string(),
+            ).into());
+        }
+        let url = format!("{}/images/generations", self.base_url);
+
+        let request_body = GenerationRequest {
+            model: &request.model,
+            prompt: &request.prompt,
+            n: 1,
+            size: self.size.as_deref(),
+            response_format: Self::response_format(&request.model),
+        };
+
+        log_request("Images API 请求", &request.model, &request_body, self.log_payloads);
+        let response = self.client.post(&url).json(&request_body).send().await
+            .map_err(request_error)?;
+
+        self.parse_response(response, &request.model).await
+    }
+
+    async fn edit(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        let url = format!("{}/images/edits", self.base_url);
+
+        let mut form = reqwest::multipart::Form::new()
+            .text("model", request.model.clone())
+            .text("prompt", request.prompt.clone())
+            .text("n", "1");
+        if let Some(size) = &self.size {
+            form = form.text("size", size.clone());
+        }
+        if let Some(response_format) = Self::response_format(&request.model) {
+            form = form.text("response_format", response_format);
+        }
+
+        // 多张图像使用 image[] 字段，单张图像使用 image 字段以兼容 dall-e-2
+        let field_name = if request.images.len() > 1 { "image[]" } else { "image" };
+        for (index, image) in request.images.iter().enumerate() {
+            let (mime_type, data) = image_utils::split_data_url(image).ok_or_else(|| {
+                McpError::from(ImageError::InvalidImage(
+                    format!("Images API 后端只支持 base64 或本地文件图像输入，无法使用: {}", image),
+                ))
+            })?;
+            let bytes = general_purpose::STANDARD.decode(data)
+                .map_err(|e| McpError::from(ImageError::InvalidImage(format!("base64解码失败: {}", e))))?;
+            let extension = mime_type.trim_start_matches("image/").split('+').next().unwrap_or("png");
+            let part = reqwest::multipart::Part::bytes(bytes)
+                .file_name(format!("image_{}.{}", index + 1, extension))
+                .mime_str(mime_type)
+                .map_err(|e| McpError::from(ImageError::InvalidImage(format!("无效的图像MIME类型: {}", e))))?;
+            form = form.part(field_name, part);
+        }
+
+        // multipart 表单无法直接序列化，按等价的 JSON 记录
+        log_request("Images API 请求", &request.model, &json!({
+            "model": request.model,
+            "prompt": request.prompt,
+            "size": self.size,
+            "response_format": Self::response_format(&request.model),
+            field_name: request.images,
+        }), self.log_payloads);
+        let response = self.client.post(&url).multipart(form).send().await
+            .map_err(request_error)?;
+
+        self.parse_response(response, &request.model).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<OpenRouterModel>, McpError> {
+        let url = format!("{}/models", self.base_url);
+
+        let response = self.client.get(&url).send().await
+            .map_err(request_error)?;
+
+        let models: OpenAiModelsResponse = read_json(check_status(response, "API").await?, "模型列表").await?;
+
+        Ok(models.data.into_iter().map(|model| {
+            // OpenAI 的模型列表不包含模态信息，按模型名称判断是否为图像模型
+            let is_image_model = model.id.contains("image") || model.id.starts_with("dall-e");
+            let output_modalities = if is_image_model {
+                vec!["image".to_string()]
+            } else {
+                vec!["text".to_string()]
+            };
+            OpenRouterModel {
+                name: model.id.clone(),
+                id: model.id,
+                description: None,
+                context_length: None,
+                pricing: None,
+                top_provider: None,
+                architecture: Some(ModelArchitecture {
+                    modality: None,
+                    input_modalities: None,
+                    output_modalities: Some(output_modalities),
+                }),
+            }
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::test_support::{http_config, request};
+    use axum::extract::State;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use std::sync::{Arc, Mutex};
+
+    const MODEL: &str = "gpt-image-1";
+
+    /// 桩服务器：生成和编辑接口返回固定的 JSON（`{base}` 替换为服务器地址），并记录编辑请求体
+    struct Stub {
+        base_url: String,
+        edit_bodies: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn spawn_stub(response: &'static str) -> Stub {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let body = response.replace("{base}", &base);
+        let edit_bodies = Arc::new(Mutex::new(Vec::new()));
+
+        let json_response = move || {
+            let body = body.clone();
+            async move { ([(axum::http::header::CONTENT_TYPE, "application/json")], body) }
+        };
+        let app = Router::new()
+            .route("/v1/images/generations", post(json_response.clone()))
+            .route("/v1/images/edits", post(move |State(bodies): State<Arc<Mutex<Vec<String>>>>, body: axum::body::Bytes| async move {
+                bodies.lock().unwrap().push(String::from_utf8_lossy(&body).into_owned());
+                json_response().await
+            }))
+            .route("/result.png", get(|| async {
+                ([(axum::http::header::CONTENT_TYPE, "image/png")], image_utils::placeholder_png(7, 8))
+            }))
+            .with_state(edit_bodies.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Stub { base_url: format!("{}/v1", base), edit_bodies }
+    }
+
+    fn backend(stub: &Stub, max_bytes: u64) -> OpenAiImagesBackend {
+        let config = OpenAiImagesConfig {
+            api_key: Some("sk-test".to_string()),
+            base_url: stub.base_url.clone(),
+            model: MODEL.to_string(),
+            supported_models: vec![MODEL.to_string()],
+            size: None,
+        };
+        let url_fetch = UrlFetchConfig {
+            fetch: false,
+            max_bytes,
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+        };
+        OpenAiImagesBackend::new(&config, &http_config(), &url_fetch, PayloadLogging::Off).unwrap()
+    }
+
+    fn input_image() -> String {
+        format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(image_utils::placeholder_png(1, 8)))
+    }
+
+    #[tokio::test]
+    async fn b64_json_result_becomes_png_data_url() {
+        let stub = spawn_stub(r#"{"data": [{"b64_json": "iVBORw0KGgo=", "revised_prompt": "一只橘猫"}],
+            "usage": {"input_tokens": 10, "output_tokens": 20, "total_tokens": 30}}"#).await;
+
+        let result = backend(&stub, 1024 * 1024).generate(&request(MODEL, "一只猫")).await.unwrap();
+        assert_eq!(result.images, vec!["data:image/png;base64,iVBORw0KGgo=".to_string()]);
+        assert_eq!(result.text, "修订后的提示词: 一只橘猫");
+        assert_eq!(result.usage.unwrap().total_tokens, 30);
+    }
+
+    #[tokio::test]
+    async fn output_format_sets_mime_type() {
+        let stub = spawn_stub(r#"{"data": [{"b64_json": "/9j/4AAQ"}], "output_format": "jpeg"}"#).await;
+
+        let result = backend(&stub, 1024 * 1024).generate(&request(MODEL, "一只猫")).await.unwrap();
+        assert_eq!(result.images, vec!["data:image/jpeg;base64,/9j/4AAQ".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn url_result_is_downloaded() {
+        let stub = spawn_stub(r#"{"data": [{"url": "{base}/result.png"}]}"#).await;
+
+        let result = backend(&stub, 1024 * 1024).generate(&request(MODEL, "一只猫")).await.unwrap();
+        let expected = general_purpose::STANDARD.encode(image_utils::placeholder_png(7, 8));
+        assert_eq!(result.images, vec![format!("data:image/png;base64,{}", expected)]);
+    }
+
+    #[tokio::test]
+    async fn url_result_over_size_limit_is_rejected() {
+        let stub = spawn_stub(r#"{"data": [{"url": "{base}/result.png"}]}"#).await;
+
+        let error = backend(&stub, 16).generate(&request(MODEL, "一只猫")).await.unwrap_err();
+        assert!(error.message.contains("超过下载大小上限"), "{}", error.message);
+    }
+
+    #[tokio::test]
+    async fn url_result_on_private_address_is_rejected() {
+        let stub = spawn_stub(r#"{"data": [{"url": "http://169.254.169.254/latest/meta-data/"}]}"#).await;
+
+        let error = backend(&stub, 1024 * 1024).generate(&request(MODEL, "一只猫")).await.unwrap_err();
+        assert_eq!(error.data.unwrap()["kind"], serde_json::json!("url_not_allowed"));
+    }
+
+    #[tokio::test]
+    async fn generate_rejects_input_images() {
+        let stub = spawn_stub(r#"{"data": []}"#).await;
+        let request = ImageRequest { images: vec![input_image()], ..request(MODEL, "一只猫") };
+
+        let error = backend(&stub, 1024 * 1024).generate(&request).await.unwrap_err();
+        assert_eq!(error.data.unwrap()["kind"], serde_json::json!("invalid_params"));
+    }
+
+    #[tokio::test]
+    async fn edit_uses_image_field_for_one_input_and_array_field_for_several() {
+        let stub = spawn_stub(r#"{"data": [{"b64_json": "iVBORw0KGgo="}]}"#).await;
+        let backend = backend(&stub, 1024 * 1024);
+
+        backend.edit(&ImageRequest { images: vec![input_image()], ..request(MODEL, "戴帽子") }).await.unwrap();
+        backend.edit(&ImageRequest { images: vec![input_image(), input_image()], ..request(MODEL, "戴帽子") }).await.unwrap();
+
+        let bodies = stub.edit_bodies.lock().unwrap();
+        assert_eq!(bodies[0].matches("name=\"image\"").count(), 1);
+        assert!(!bodies[0].contains("name=\"image[]\""));
+        assert_eq!(bodies[1].matches("name=\"image[]\"").count(), 2);
+        assert!(!bodies[1].contains("name=\"image\";"));
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use rmcp::ErrorData as McpError;
+
+use super::openrouter_client::OpenRouterClient;
+use super::{ImageBackend, ImageRequest, ImageResult};
+use crate::config::{ChatContentPart, ChatMessage, ChatRequest, ImageUrl, OpenRouterConfig, OpenRouterModel};
+use crate::error::ImageError;
+
+/// 通过 OpenRouter chat/completions 接口生成和编辑图像
+pub struct OpenRouterBackend {
+    model: String,
+    supported_models: Vec<String>,
+    fallback_models: Vec<String>,
+    client: OpenRouterClient,
+}
+
+impl OpenRouterBackend {
+    pub fn new(config: &OpenRouterConfig) -> Result<Self> {
+        Ok(Self {
+            model: config.model.clone(),
+            supported_models: config.supported_models.clone(),
+            fallback_models: config.fallback_models.clone(),
+            client: OpenRouterClient::new(config)?,
+        })
+    }
+
+    /// 通过 /models 在线校验允许的模型列表，移除不存在或不能输出图像的模型
+    pub async fn validate_supported_models(&mut self) -> Result<()> {
+        let models = self.list_models().await
+            .map_err(|e| anyhow::anyhow!("在线校验模型列表失败: {}", e.message))?;
+
+        let mut valid_models = Vec::new();
+        for model_id in &self.supported_models {
+            match models.iter().find(|m| &m.id == model_id) {
+                Some(model) if model.supports_image_output() => valid_models.push(model_id.clone()),
+                Some(_) => tracing::warn!("模型 {} 不支持图像输出，已从允许列表中移除", model_id),
+                None => tracing::warn!("OpenRouter 上找不到模型 {}，已从允许列表中移除", model_id),
+            }
+        }
+
+        if !valid_models.contains(&self.model) {
+            return Err(anyhow::anyhow!("默认模型 {} 未通过在线校验", self.model));
+        }
+
+        self.fallback_models.retain(|model| {
+            let valid = valid_models.contains(model);
+            if !valid {
+                tracing::warn!("回退模型 {} 未通过在线校验，已从回退列表中移除", model);
+            }
+            valid
+        });
+
+        tracing::info!("在线校验后允许的模型: {}", valid_models.join(", "));
+        self.supported_models = valid_models;
+        Ok(())
+    }
+
+    /// 发送一次 chat/completions 请求，输入图像作为 image_url 内容附加在提示词之后
+    async fn chat_completion(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        let mut content = vec![ChatContentPart::Text { text: request.prompt.clone() }];
+        content.extend(request.images.iter().map(|image| ChatContentPart::ImageUrl {
+            image_url: ImageUrl { url: image.clone(), detail: None },
+        }));
+
+        let chat_request = ChatRequest {
+            model: request.model.clone(),
+            messages: vec![ChatMessage {
+                role: "user".to_string(),
+                content: serde_json::to_value(content).unwrap_or_default(),
+            }],
+            max_tokens: Some(request.max_tokens),
+            temperature: Some(request.temperature),
+        };
+
+        let response = self.client.chat(&chat_request).await?;
+
+        let choice = response.choices.into_iter().next().ok_or_else(|| McpError::from(ImageError::InvalidResponse {
+            message: "API 响应中 'choices' 数组为空".to_string(),
+            body: None,
+        }))?;
+
+        let text = match (choice.message.content, choice.finish_reason) {
+            (Some(content), _) => content,
+            (None, Some(reason)) => format!("无内容 (结束原因: {})", reason),
+            (None, None) => "无内容".to_string(),
+        };
+
+        let images = choice.message.images
+            .unwrap_or_default()
+            .into_iter()
+            .map(|image| image.image_url.url)
+            .collect();
+
+        Ok(ImageResult { text, images, usage: response.usage })
+    }
+}
+
+#[async_trait]
+impl ImageBackend for OpenRouterBackend {
+    fn name(&self) -> &'static str {
+        "openrouter"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.model
+    }
+
+    fn supported_models(&self) -> &[String] {
+        &self.supported_models
+    }
+
+    fn fallback_models(&self) -> &[String] {
+        &self.fallback_models
+    }
+
+    async fn generate(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        self.chat_completion(request).await
+    }
+
+    async fn edit(&self, request: &ImageRequest) -> Result<ImageResult, McpError> {
+        self.chat_completion(request).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<OpenRouterModel>, McpError> {
+        self.client.models().await
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use rmcp::ErrorData as McpError;
+
+use super::{build_client, check_status, http_error, log_request, parse_body, read_logged_json, request_error};
+use crate::config::{ChatError, ChatRequest, ChatResponse, ModelsResponse, OpenRouterConfig, OpenRouterModel, PayloadLogging};
+use crate::error::{ImageError, UpstreamError};
+
+/// OpenRouter HTTP 客户端，请求和响应都通过 config.rs 中的类型序列化
+pub struct OpenRouterClient {
+    base_url: String,
+    client: reqwest::Client,
+    log_payloads: PayloadLogging,
+}
+
+impl OpenRouterClient {
+    pub fn new(config: &OpenRouterConfig) -> Result<Self> {
+        Ok(Self {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            client: build_client(&config.http, config.get_headers())?,
+            log_payloads: config.log_payloads,
+        })
+    }
+
+    /// 调用 /chat/completions，响应中的 `error` 对象会转换为错误
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse, McpError> {
+        let url = format!("{}/chat/completions", self.base_url);
+
+        tracing::debug!(model = %request.model, messages = request.messages.len(), "发送 OpenRouter 请求");
+        log_request("OpenRouter 请求", &request.model, request, self.log_payloads);
+
+        let response = self.client.post(&url).json(request).send().await
+            .map_err(request_error)?;
+
+        // 首先检查 HTTP 状态码
+        let response: ChatResponse = read_logged_json(
+            check_status(response, "API").await?,
+            "OpenRouter 响应",
+            &request.model,
+            self.log_payloads,
+        ).await?;
+
+        // 错误可能出现在顶层，也可能出现在单个 choice 中
+        let error = response.error.as_ref()
+            .or_else(|| response.choices.iter().find_map(|choice| choice.error.as_ref()));
+        if let Some(error) = error {
+            return Err(provider_error(error));
+        }
+
+        Ok(response)
+    }
+
+    /// 调用 /models 获取模型列表
+    pub async fn models(&self) -> Result<Vec<OpenRouterModel>, McpError> {
+        let url = format!("{}/models", self.base_url);
+
+        let response = self.client.get(&url).send().await
+            .map_err(request_error)?;
+
+        let body = check_status(response, "API").await?.text().await
+            .map_err(|e| http_error("读取模型列表失败", e))?;
+
+        parse_body::<ModelsResponse>(&body, "模型列表").map(|models| models.data)
+    }
+}
+
+/// 上游提供商的限流等错误可能以 200 状态码返回，错误码与 HTTP 状态码一致
+fn provider_error(error: &ChatError) -> McpError {
+    ImageError::upstream(UpstreamError {
+        service: "API".to_string(),
+        status: error.status(),
+        message: error.message.clone(),
+        retry_after_ms: None,
+        provider_error: true,
+        metadata: error.metadata.clone(),
+    }).into()
+}
@@ -0,0 +1,231 @@
+use rmcp::ErrorData as McpError;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::config::RetryConfig;
+use crate::error;
+
+/// 一次上游请求尝试的记录，会出现在工具结果和错误数据中
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestAttempt {
+    /// 从 1 开始的尝试序号
+    pub attempt: u32,
+    pub elapsed_ms: u64,
+    /// 失败时的 HTTP 状态码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 重试前等待的时间，最后一次尝试为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay_ms: Option<u64>,
+}
+
+/// 请求超时、限流和服务端临时错误可以重试，其余状态码重试也不会成功
+pub fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// 解析 Retry-After 头：秒数或 HTTP 日期
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(delay.to_std().unwrap_or_default())
+}
+
+/// 执行一次后端调用，对可重试的错误按指数退避重试
+///
+/// 错误是否可重试由后端在错误数据中的 `retryable` 字段决定，`retry_after_ms` 优先于退避时间。
+/// 成功时返回所有尝试记录；最终失败时尝试记录放入错误数据的 `attempts` 字段。
+pub async fn with_retries<T, F, Fut>(
+    config: &RetryConfig,
+    mut operation: F,
+) -> Result<(T, Vec<RequestAttempt>), McpError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, McpError>>,
+{
+    let mut attempts = Vec::new();
+    loop {
+        let attempt = attempts.len() as u32 + 1;
+        let started = Instant::now();
+        let result = operation().await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        let mut error = match result {
+            Ok(value) => {
+                attempts.push(RequestAttempt {
+                    attempt,
+                    elapsed_ms,
+                    status: None,
+                    error: None,
+                    retry_delay_ms: None,
+                });
+                return Ok((value, attempts));
+            }
+            Err(error) => error,
+        };
+
+        let data = error.data.as_ref();
+        let status = data
+            .and_then(|d| d.get("status"))
+            .and_then(Value::as_u64)
+            .map(|s| s as u16);
+        let retryable = data
+            .and_then(|d| d.get("retryable"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let retry_after = data
+            .and_then(|d| d.get("retry_after_ms"))
+            .and_then(Value::as_u64)
+            .map(Duration::from_millis);
+
+        let delay = if retryable && attempt <= config.max_retries {
+            retry_delay(config, attempt, retry_after)
+        } else {
+            None
+        };
+
+        attempts.push(RequestAttempt {
+            attempt,
+            elapsed_ms,
+            status,
+            error: Some(error.message.to_string()),
+            retry_delay_ms: delay.map(|d| d.as_millis() as u64),
+        });
+
+        let Some(delay) = delay else {
+            error::insert_data(&mut error, "attempts", json!(attempts));
+            return Err(error);
+        };
+
+        tracing::warn!(
+            attempt,
+            error = %error.message,
+            delay_ms = delay.as_millis() as u64,
+            "请求失败，稍后重试"
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// 计算第 `attempt` 次失败后的等待时间
+fn retry_delay(config: &RetryConfig, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+    if let Some(retry_after) = retry_after {
+        // 服务器要求的等待时间超过上限时直接放弃，避免工具调用长时间挂起
+        return (retry_after <= config.max_delay).then_some(retry_after);
+    }
+
+    // 指数退避加等量抖动：一半固定，一半随机，避免并发请求同时重试
+    let backoff = config
+        .base_delay
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(config.max_delay);
+    let half = backoff / 2;
+    Some(half + half.mul_f64(random_fraction()))
+}
+
+/// [0, 1) 之间的随机数，使用标准库的随机哈希种子，无需额外依赖
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn config(max_retries: u32) -> RetryConfig {
+        RetryConfig {
+            max_retries,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+
+    fn failure(retryable: bool) -> McpError {
+        McpError::internal_error("上游错误", Some(json!({ "status": 503, "retryable": retryable })))
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_http_date() {
+        assert_eq!(parse_retry_after(" 7 "), Some(Duration::from_secs(7)));
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let delay = parse_retry_after(&date).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30), "{:?}", delay);
+        // 已经过去的日期不需要等待
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_beyond_max_delay_gives_up() {
+        let config = config(3);
+        assert_eq!(retry_delay(&config, 1, Some(Duration::from_millis(500))), Some(Duration::from_millis(500)));
+        assert_eq!(retry_delay(&config, 1, Some(Duration::from_secs(2))), None);
+
+        // 没有 Retry-After 时按指数退避，不超过上限
+        let delay = retry_delay(&config, 20, None).unwrap();
+        assert!(delay >= Duration::from_millis(500) && delay <= config.max_delay, "{:?}", delay);
+    }
+
+    #[tokio::test]
+    async fn non_retryable_errors_are_not_retried() {
+        let calls = Cell::new(0);
+        let error = with_retries(&config(3), || {
+            calls.set(calls.get() + 1);
+            async { Err::<(), _>(failure(false)) }
+        }).await.unwrap_err();
+
+        assert_eq!(calls.get(), 1);
+        let attempts = error.data.unwrap()["attempts"].clone();
+        assert_eq!(attempts.as_array().unwrap().len(), 1);
+        assert!(attempts[0].get("retry_delay_ms").is_none());
+    }
+
+    #[tokio::test]
+    async fn final_failure_records_every_attempt() {
+        let calls = Cell::new(0);
+        let error = with_retries(&config(2), || {
+            calls.set(calls.get() + 1);
+            async { Err::<(), _>(failure(true)) }
+        }).await.unwrap_err();
+
+        assert_eq!(calls.get(), 3);
+        let data = error.data.unwrap();
+        let attempts = data["attempts"].as_array().unwrap();
+        assert_eq!(attempts.len(), 3);
+        for (index, attempt) in attempts.iter().enumerate() {
+            assert_eq!(attempt["attempt"], index + 1);
+            assert_eq!(attempt["status"], 503);
+            assert_eq!(attempt["error"], "上游错误");
+        }
+        assert!(attempts[1].get("retry_delay_ms").is_some());
+        assert!(attempts[2].get("retry_delay_ms").is_none());
+    }
+
+    #[tokio::test]
+    async fn succeeds_after_retryable_failure() {
+        let calls = Cell::new(0);
+        let (value, attempts) = with_retries(&config(3), || {
+            calls.set(calls.get() + 1);
+            let call = calls.get();
+            async move { if call == 1 { Err(failure(true)) } else { Ok(call) } }
+        }).await.unwrap();
+
+        assert_eq!(value, 2);
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].status, Some(503));
+        assert!(attempts[1].error.is_none());
+    }
+}
@@ -1,15 +1,43 @@
 use anyhow::{anyhow, Result};
+use rmcp::schemars;
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// 默认允许的模型列表
+pub const DEFAULT_SUPPORTED_MODELS: &[&str] = &[
+    "google/gemini-2.5-flash-image-preview:free",
+    "google/gemini-2.5-flash-image-preview",
+];
 
 #[derive(Debug, Clone)]
 pub struct OpenRouterConfig {
-    pub api_key: String,
+    pub api_key: Option<String>,
     pub base_url: String,
     pub http_referer: String,
     pub x_title: String,
     pub http_port: u16,
+    pub backend: String,
     pub model: String,
+    pub supported_models: Vec<String>,
+    /// 主模型失败时按顺序尝试的回退模型
+    pub fallback_models: Vec<String>,
+    pub validate_models_live: bool,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub retry: RetryConfig,
+    pub rate_limit: RateLimitConfig,
+    pub http: HttpConfig,
+    /// 允许读取本地输入图像的根目录；为空时只允许当前保存目录
+    pub input_roots: Vec<PathBuf>,
+    pub url_fetch: UrlFetchConfig,
+    pub input_images: InputImageConfig,
+    /// 是否在日志中输出上游请求和响应体
+    pub log_payloads: PayloadLogging,
+    pub gemini: Option<GeminiConfig>,
+    pub openai_images: Option<OpenAiImagesConfig>,
+    pub local_sd: Option<LocalSdConfig>,
 }
 
 impl OpenRouterConfig {
@@ -18,9 +46,8 @@ impl OpenRouterConfig {
 
         // 首先尝试从命令行参数获取 API key
         let args: Vec<String> = env::args().collect();
-        let api_key = Self::get_api_key_from_args(&args)
-            .or_else(|| env::var("OPENROUTER_API_KEY").ok())
-            .ok_or_else(|| anyhow!("OPENROUTER_API_KEY 环境变量或 --api-key 命令行参数是必需的"))?;
+        let api_key = Self::get_arg_value(&args, "--api-key")
+            .or_else(|| env::var("OPENROUTER_API_KEY").ok());
 
         let base_url = env::var("OPENROUTER_BASE_URL")
             .unwrap_or_else(|_| "https://openrouter.ai/api/v1".to_string());
@@ -31,71 +58,154 @@ impl OpenRouterConfig {
         let x_title = env::var("X_TITLE")
             .unwrap_or_else(|_| "OpenRouter MCP Server (Rust)".to_string());
 
-        let http_port = env::var("MCP_HTTP_PORT")
-            .unwrap_or_else(|_| "6621".to_string())
-            .parse()
-            .unwrap_or(6621);
+        let http_port = parse_number(&args, None, "MCP_HTTP_PORT", 6621, any_value)?;
+
+        // 获取后端配置：优先命令行参数，然后环境变量，最后默认值
+        let backend = Self::get_arg_value(&args, "--backend")
+            .or_else(|| env::var("MCP_BACKEND").ok())
+            .unwrap_or_else(|| "openrouter".to_string());
+
+        // 只有使用 OpenRouter 作为默认后端时才必须提供 OpenRouter API key
+        if api_key.is_none() && backend == "openrouter" {
+            return Err(anyhow!("OPENROUTER_API_KEY 环境变量或 --api-key 命令行参数是必需的"));
+        }
+
+        let gemini = GeminiConfig::from_env(&args)?;
+        let openai_images = OpenAiImagesConfig::from_env(&args)?;
+        let local_sd = LocalSdConfig::from_env(&args)?;
 
         // 获取模型配置：优先命令行参数，然后环境变量，最后默认值
-        let model = Self::get_model_from_args(&args)
+        let model = Self::get_arg_value(&args, "--model")
             .or_else(|| env::var("MCP_MODEL").ok())
             .unwrap_or_else(|| "google/gemini-2.5-flash-image-preview:free".to_string());
 
+        // 获取允许的模型列表：优先命令行参数，然后环境变量，最后默认值
+        let supported_models = Self::get_arg_value(&args, "--supported-models")
+            .or_else(|| env::var("MCP_SUPPORTED_MODELS").ok())
+            .map(|list| Self::parse_model_list(&list))
+            .filter(|models| !models.is_empty())
+            .unwrap_or_else(|| {
+                DEFAULT_SUPPORTED_MODELS.iter().map(|m| m.to_string()).collect()
+            });
+
+        // 是否在启动时通过 /models 在线校验允许的模型列表
+        let validate_models_live = args.iter().any(|arg| arg == "--validate-models")
+            || env::var("MCP_VALIDATE_MODELS")
+                .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false);
+
         // 验证模型是否在支持的列表中
-        let supported_models = vec![
-            "google/gemini-2.5-flash-image-preview:free".to_string(),
-            "google/gemini-2.5-flash-image-preview".to_string(),
-        ];
-        
         if !supported_models.contains(&model) {
             return Err(anyhow!("不支持的模型: {}。支持的模型: {}", 
                 model, 
                 supported_models.join(", ")));
         }
 
+        // 获取回退模型列表：主模型限流、返回错误或没有返回图像时按顺序尝试
+        let fallback_models = Self::get_arg_value(&args, "--fallback-models")
+            .or_else(|| env::var("MCP_FALLBACK_MODELS").ok())
+            .map(|list| Self::parse_model_list(&list))
+            .unwrap_or_default();
+
+        if let Some(model) = fallback_models.iter().find(|m| !supported_models.contains(m)) {
+            return Err(anyhow!("回退模型 {} 不在允许的模型列表中。支持的模型: {}",
+                model,
+                supported_models.join(", ")));
+        }
+
+        // 请求参数默认值，可被每次工具调用覆盖；与工具参数使用相同的范围检查
+        let max_tokens = parse_number(&args, None, "MCP_MAX_TOKENS", 1000, check_max_tokens)?;
+        let temperature = parse_number(&args, None, "MCP_TEMPERATURE", 0.7, check_temperature)?;
+
+        let retry = RetryConfig::from_env(&args)?;
+        let rate_limit = RateLimitConfig::from_env(&args)?;
+        let http = HttpConfig::from_env(&args)?;
+        let url_fetch = UrlFetchConfig::from_env(&args)?;
+        let input_images = InputImageConfig::from_env(&args)?;
+
+        // 上游请求和响应体的日志：off（默认）、redacted（base64 替换为摘要）或 full
+        let log_payloads = match Self::get_arg_value(&args, "--log-payloads")
+            .or_else(|| env::var("MCP_LOG_PAYLOADS").ok())
+            .unwrap_or_else(|| "off".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "off" | "0" | "false" => PayloadLogging::Off,
+            "redacted" | "1" | "true" => PayloadLogging::Redacted,
+            "full" => PayloadLogging::Full,
+            other => return Err(anyhow!("不支持的请求体日志模式: {}。可选: off, redacted, full", other)),
+        };
+
+        // 允许读取的本地输入图像根目录，使用系统路径分隔符分隔（Unix 为 ':'，Windows 为 ';'）
+        let input_roots: Vec<PathBuf> = Self::get_arg_value(&args, "--input-roots")
+            .or_else(|| env::var("MCP_INPUT_ROOTS").ok())
+            .map(|roots| env::split_paths(&roots).filter(|root| !root.as_os_str().is_empty()).collect())
+            .unwrap_or_default();
+
+        if let Some(root) = input_roots.iter().find(|root| !root.is_absolute()) {
+            return Err(anyhow!("输入图像根目录必须是绝对路径，当前提供: {}", root.display()));
+        }
+
         Ok(Self {
             api_key,
             base_url,
             http_referer,
             x_title,
             http_port,
+            backend,
             model,
+            supported_models,
+            fallback_models,
+            validate_models_live,
+            max_tokens,
+            temperature,
+            retry,
+            rate_limit,
+            http,
+            input_roots,
+            url_fetch,
+            input_images,
+            log_payloads,
+            gemini,
+            openai_images,
+            local_sd,
         })
     }
 
-    /// 从命令行参数中获取 API key
-    fn get_api_key_from_args(args: &[String]) -> Option<String> {
+    /// 从命令行参数中获取 `--name value` 或 `--name=value` 形式的值
+    fn get_arg_value(args: &[String], name: &str) -> Option<String> {
+        let prefix = format!("{}=", name);
         for (i, arg) in args.iter().enumerate() {
-            if arg == "--api-key" && i + 1 < args.len() {
+            if arg == name && i + 1 < args.len() {
                 return Some(args[i + 1].clone());
             }
-            if arg.starts_with("--api-key=") {
-                return Some(arg.trim_start_matches("--api-key=").to_string());
+            if let Some(value) = arg.strip_prefix(&prefix) {
+                return Some(value.to_string());
             }
         }
         None
     }
 
-    /// 从命令行参数中获取模型
-    fn get_model_from_args(args: &[String]) -> Option<String> {
-        for (i, arg) in args.iter().enumerate() {
-            if arg == "--model" && i + 1 < args.len() {
-                return Some(args[i + 1].clone());
-            }
-            if arg.starts_with("--model=") {
-                return Some(arg.trim_start_matches("--model=").to_string());
+    /// 解析逗号分隔的模型列表，忽略空项和重复项
+    fn parse_model_list(list: &str) -> Vec<String> {
+        let mut models: Vec<String> = Vec::new();
+        for model in list.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            if !models.iter().any(|m| m == model) {
+                models.push(model.to_string());
             }
         }
-        None
+        models
     }
 
     pub fn get_headers(&self) -> reqwest::header::HeaderMap {
         let mut headers = reqwest::header::HeaderMap::new();
         
-        headers.insert(
-            reqwest::header::AUTHORIZATION,
-            format!("Bearer {}", self.api_key).parse().unwrap(),
-        );
+        if let Some(api_key) = &self.api_key {
+            let mut value: reqwest::header::HeaderValue = format!("Bearer {}", api_key).parse().unwrap();
+            // 标记为敏感值，调试输出中不显示
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
         headers.insert(
             reqwest::header::HeaderName::from_static("http-referer"),
             self.http_referer.parse().unwrap(),
@@ -113,6 +223,491 @@ impl OpenRouterConfig {
     }
 }
 
+/// 上游请求的重试配置，适用于所有后端
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// 首次请求失败后的最大重试次数，0 表示不重试
+    pub max_retries: u32,
+    /// 第一次重试前的基础退避时间，之后每次翻倍
+    pub base_delay: Duration,
+    /// 单次等待时间上限，Retry-After 超过该值时不再重试
+    pub max_delay: Duration,
+}
+
+impl RetryConfig {
+    fn from_env(args: &[String]) -> Result<Self> {
+        let max_retries = parse_number(args, Some("--max-retries"), "MCP_MAX_RETRIES", 3, any_value)?;
+        let base_delay_ms = parse_number(args, None, "MCP_RETRY_BASE_DELAY_MS", 1000, any_value)?;
+        let max_delay_ms = parse_number(args, None, "MCP_RETRY_MAX_DELAY_MS", 30_000, any_value)?;
+
+        Ok(Self {
+            max_retries,
+            base_delay: Duration::from_millis(base_delay_ms),
+            max_delay: Duration::from_millis(max_delay_ms),
+        })
+    }
+}
+
+/// 所有后端共用的 HTTP 客户端配置
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// 建立 TCP/TLS 连接的超时时间
+    pub connect_timeout: Duration,
+    /// 单次请求从发送到读完响应的总超时时间
+    pub request_timeout: Duration,
+    /// HTTP(S) 或 SOCKS5 代理地址，例如 `http://proxy:8080`、`socks5h://127.0.0.1:1080`
+    pub proxy: Option<String>,
+}
+
+impl HttpConfig {
+    fn from_env(args: &[String]) -> Result<Self> {
+        // 超时为 0 时每个请求都会立即失败
+        let connect_timeout_secs = parse_number(args, Some("--connect-timeout"), "MCP_CONNECT_TIMEOUT_SECS", 10, positive)?;
+        let request_timeout_secs = parse_number(args, Some("--request-timeout"), "MCP_REQUEST_TIMEOUT_SECS", 180, positive)?;
+
+        let proxy = OpenRouterConfig::get_arg_value(args, "--proxy")
+            .or_else(|| env::var("MCP_PROXY").ok())
+            .filter(|proxy| !proxy.is_empty());
+
+        Ok(Self {
+            connect_timeout: Duration::from_secs(connect_timeout_secs),
+            request_timeout: Duration::from_secs(request_timeout_secs),
+            proxy,
+        })
+    }
+}
+
+/// 检查 max_tokens 的取值范围，启动配置和单次调用的覆盖参数共用
+pub fn check_max_tokens(max_tokens: u32) -> std::result::Result<u32, String> {
+    if max_tokens == 0 {
+        return Err("max_tokens 必须大于 0".to_string());
+    }
+    Ok(max_tokens)
+}
+
+/// 检查 temperature 的取值范围，启动配置和单次调用的覆盖参数共用
+pub fn check_temperature(temperature: f32) -> std::result::Result<f32, String> {
+    if !(0.0..=2.0).contains(&temperature) {
+        return Err(format!("temperature 必须在 0.0 到 2.0 之间，当前: {}", temperature));
+    }
+    Ok(temperature)
+}
+
+/// 读取数值配置，命令行参数 `flag` 优先于环境变量 `env_name`
+///
+/// 都未设置时使用默认值；无法解析或超出范围时报错，避免错误的配置被静默替换为默认值
+fn parse_number<T>(
+    args: &[String],
+    flag: Option<&str>,
+    env_name: &str,
+    default: T,
+    check: fn(T) -> std::result::Result<T, String>,
+) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (source, value) = match flag.and_then(|flag| OpenRouterConfig::get_arg_value(args, flag).map(|value| (flag, value))) {
+        Some(found) => found,
+        None => match env::var(env_name) {
+            Ok(value) => (env_name, value),
+            Err(_) => return Ok(default),
+        },
+    };
+    let parsed = value.trim().parse::<T>()
+        .map_err(|e| anyhow!("{} 的值 '{}' 无效: {}", source, value, e))?;
+    check(parsed).map_err(|e| anyhow!("{} 的值 '{}' 无效: {}", source, value, e))
+}
+
+/// 不限制取值范围
+fn any_value<T>(value: T) -> std::result::Result<T, String> {
+    Ok(value)
+}
+
+/// 必须大于 0
+fn positive<T: PartialOrd + Default>(value: T) -> std::result::Result<T, String> {
+    if value > T::default() {
+        Ok(value)
+    } else {
+        Err("必须大于 0".to_string())
+    }
+}
+
+/// URL 图像输入的处理配置
+#[derive(Debug, Clone)]
+pub struct UrlFetchConfig {
+    /// 由服务器下载 URL 图像并以 data URL 发送给后端，而不是把 URL 直接交给模型
+    pub fetch: bool,
+    /// 单张下载图像的最大字节数
+    pub max_bytes: u64,
+    /// 允许的域名（包含子域名），为空时允许所有域名
+    pub allowed_domains: Vec<String>,
+    /// 拒绝的域名（包含子域名），优先于允许列表
+    pub denied_domains: Vec<String>,
+}
+
+impl UrlFetchConfig {
+    fn from_env(args: &[String]) -> Result<Self> {
+        let fetch = args.iter().any(|arg| arg == "--fetch-urls")
+            || env::var("MCP_FETCH_URLS")
+                .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false);
+
+        let max_bytes = parse_number(args, None, "MCP_FETCH_MAX_BYTES", 20 * 1024 * 1024, positive)?;
+
+        let allowed_domains = OpenRouterConfig::get_arg_value(args, "--url-allowed-domains")
+            .or_else(|| env::var("MCP_URL_ALLOWED_DOMAINS").ok())
+            .map(|list| Self::parse_domain_list(&list))
+            .unwrap_or_default();
+
+        let denied_domains = OpenRouterConfig::get_arg_value(args, "--url-denied-domains")
+            .or_else(|| env::var("MCP_URL_DENIED_DOMAINS").ok())
+            .map(|list| Self::parse_domain_list(&list))
+            .unwrap_or_default();
+
+        Ok(Self {
+            fetch,
+            max_bytes,
+            allowed_domains,
+            denied_domains,
+        })
+    }
+
+    /// 解析逗号分隔的域名列表，`*.example.com` 和 `.example.com` 都按 `example.com` 处理
+    fn parse_domain_list(list: &str) -> Vec<String> {
+        list.split(',')
+            .map(|domain| domain.trim().trim_start_matches("*.").trim_start_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect()
+    }
+}
+
+/// 上游请求和响应体的日志模式，密钥字段在所有模式下都会遮盖
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadLogging {
+    /// 只记录大小、状态码等摘要字段
+    Off,
+    /// 记录请求和响应体，base64 图像替换为大小和哈希摘要
+    Redacted,
+    /// 记录完整的请求和响应体，包括 base64 图像
+    Full,
+}
+
+/// 输入图像的大小限制和发送前的缩放设置
+#[derive(Debug, Clone)]
+pub struct InputImageConfig {
+    /// 单张输入图像的最大字节数（按解码后的图像字节计算）
+    pub max_image_bytes: u64,
+    /// 一次请求中所有内联输入图像的最大总字节数
+    pub max_total_bytes: u64,
+    /// 启用缩放时，缩放前允许读取和解码的单张源图像最大字节数
+    pub max_source_bytes: u64,
+    /// 启用缩放时图像最长边的像素上限，为 None 时不缩放
+    pub max_dimension: Option<u32>,
+    /// 缩放或压缩后重新编码的格式
+    pub format: ReencodeFormat,
+    /// JPEG 编码质量 (1-100)
+    pub quality: u8,
+}
+
+/// 重新编码输入图像使用的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReencodeFormat {
+    Jpeg,
+    /// 无损 WebP，不使用编码质量
+    Webp,
+    Png,
+}
+
+impl ReencodeFormat {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Png => "image/png",
+        }
+    }
+}
+
+impl InputImageConfig {
+    /// 读取和解码前的单张源图像上限：不缩放时与单张上限相同
+    pub fn source_limit(&self) -> u64 {
+        match self.max_dimension {
+            Some(_) => self.max_source_bytes.max(self.max_image_bytes),
+            None => self.max_image_bytes,
+        }
+    }
+
+    fn from_env(args: &[String]) -> Result<Self> {
+        let max_image_bytes = parse_number(args, Some("--max-image-bytes"), "MCP_MAX_IMAGE_BYTES", 20 * 1024 * 1024, positive)?;
+        let max_total_bytes = parse_number(args, Some("--max-total-image-bytes"), "MCP_MAX_TOTAL_IMAGE_BYTES", 50 * 1024 * 1024, positive)?;
+        let max_source_bytes = parse_number(args, None, "MCP_MAX_SOURCE_IMAGE_BYTES", 100 * 1024 * 1024, positive)?;
+
+        // 0 或未设置表示不缩放
+        let max_dimension = Some(parse_number(args, Some("--downscale-max-dimension"), "MCP_DOWNSCALE_MAX_DIMENSION", 0u32, any_value)?)
+            .filter(|&dimension| dimension > 0);
+
+        let format = match env::var("MCP_DOWNSCALE_FORMAT")
+            .unwrap_or_else(|_| "jpeg".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "jpeg" | "jpg" => ReencodeFormat::Jpeg,
+            "webp" => ReencodeFormat::Webp,
+            "png" => ReencodeFormat::Png,
+            other => return Err(anyhow!("不支持的缩放编码格式: {}。可选: jpeg, webp, png", other)),
+        };
+
+        let quality = parse_number(args, None, "MCP_DOWNSCALE_QUALITY", 85u8, |quality| {
+            if (1..=100).contains(&quality) {
+                Ok(quality)
+            } else {
+                Err("必须在 1 到 100 之间".to_string())
+            }
+        })?;
+
+        Ok(Self {
+            max_image_bytes,
+            max_total_bytes,
+            max_source_bytes,
+            max_dimension,
+            format,
+            quality,
+        })
+    }
+}
+
+/// 上游请求的限流配置，所有会话和后端共享同一个限流器
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// 每分钟最多发送的请求数，0 表示不限制
+    pub requests_per_minute: u32,
+    /// 同时进行的最大请求数，0 表示不限制
+    pub max_concurrent_requests: usize,
+}
+
+impl RateLimitConfig {
+    fn from_env(args: &[String]) -> Result<Self> {
+        let requests_per_minute = parse_number(args, Some("--requests-per-minute"), "MCP_REQUESTS_PER_MINUTE", 0, any_value)?;
+        let max_concurrent_requests = parse_number(args, Some("--max-concurrent-requests"), "MCP_MAX_CONCURRENT_REQUESTS", 4, any_value)?;
+
+        Ok(Self {
+            requests_per_minute,
+            max_concurrent_requests,
+        })
+    }
+}
+
+/// Google Gemini API 默认允许的模型列表
+pub const DEFAULT_GEMINI_MODELS: &[&str] = &[
+    "gemini-2.5-flash-image-preview",
+    "gemini-2.0-flash-preview-image-generation",
+];
+
+/// 直连 Google Gemini API (generativelanguage) 的配置
+#[derive(Debug, Clone)]
+pub struct GeminiConfig {
+    pub api_key: String,
+    pub base_url: String,
+    pub model: String,
+    pub supported_models: Vec<String>,
+}
+
+impl GeminiConfig {
+    /// 仅在提供了 Gemini API key 时启用该后端
+    fn from_env(args: &[String]) -> Result<Option<Self>> {
+        let Some(api_key) = OpenRouterConfig::get_arg_value(args, "--gemini-api-key")
+            .or_else(|| env::var("GEMINI_API_KEY").ok())
+        else {
+            return Ok(None);
+        };
+
+        let base_url = env::var("GEMINI_BASE_URL")
+            .unwrap_or_else(|_| "https://generativelanguage.googleapis.com/v1beta".to_string());
+
+        let model = env::var("GEMINI_MODEL")
+            .unwrap_or_else(|_| DEFAULT_GEMINI_MODELS[0].to_string());
+
+        let supported_models = env::var("GEMINI_SUPPORTED_MODELS")
+            .ok()
+            .map(|list| OpenRouterConfig::parse_model_list(&list))
+            .filter(|models| !models.is_empty())
+            .unwrap_or_else(|| {
+                DEFAULT_GEMINI_MODELS.iter().map(|m| m.to_string()).collect()
+            });
+
+        if !supported_models.contains(&model) {
+            return Err(anyhow!("不支持的 Gemini 模型: {}。支持的模型: {}",
+                model,
+                supported_models.join(", ")));
+        }
+
+        Ok(Some(Self {
+            api_key,
+            base_url,
+            model,
+            supported_models,
+        }))
+    }
+}
+
+/// OpenAI 兼容 Images API 默认允许的模型列表
+pub const DEFAULT_OPENAI_IMAGES_MODELS: &[&str] = &[
+    "gpt-image-1",
+    "dall-e-3",
+    "dall-e-2",
+];
+
+/// OpenAI 兼容 /images/generations 和 /images/edits 接口的配置
+#[derive(Debug, Clone)]
+pub struct OpenAiImagesConfig {
+    pub api_key: Option<String>,
+    pub base_url: String,
+    pub model: String,
+    pub supported_models: Vec<String>,
+    pub size: Option<String>,
+}
+
+impl OpenAiImagesConfig {
+    /// 仅在提供了 Images API 基础 URL 时启用该后端
+    fn from_env(args: &[String]) -> Result<Option<Self>> {
+        let Some(base_url) = OpenRouterConfig::get_arg_value(args, "--openai-images-base-url")
+            .or_else(|| env::var("OPENAI_IMAGES_BASE_URL").ok())
+        else {
+            return Ok(None);
+        };
+
+        // 自托管服务可能不需要 API key
+        let api_key = OpenRouterConfig::get_arg_value(args, "--openai-images-api-key")
+            .or_else(|| env::var("OPENAI_IMAGES_API_KEY").ok());
+
+        let model = env::var("OPENAI_IMAGES_MODEL")
+            .unwrap_or_else(|_| DEFAULT_OPENAI_IMAGES_MODELS[0].to_string());
+
+        let supported_models = env::var("OPENAI_IMAGES_SUPPORTED_MODELS")
+            .ok()
+            .map(|list| OpenRouterConfig::parse_model_list(&list))
+            .filter(|models| !models.is_empty())
+            .unwrap_or_else(|| {
+                DEFAULT_OPENAI_IMAGES_MODELS.iter().map(|m| m.to_string()).collect()
+            });
+
+        if !supported_models.contains(&model) {
+            return Err(anyhow!("不支持的 Images API 模型: {}。支持的模型: {}",
+                model,
+                supported_models.join(", ")));
+        }
+
+        let size = env::var("OPENAI_IMAGES_SIZE").ok();
+
+        Ok(Some(Self {
+            api_key,
+            base_url,
+            model,
+            supported_models,
+            size,
+        }))
+    }
+}
+
+/// 本地 Stable Diffusion 服务的 API 类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalSdApi {
+    /// Automatic1111 WebUI 的 /sdapi/v1/txt2img 和 /sdapi/v1/img2img 接口
+    Automatic1111,
+    /// ComfyUI 的 /prompt 工作流接口
+    ComfyUi,
+}
+
+/// 本地 Stable Diffusion (ComfyUI / Automatic1111) 后端的配置
+#[derive(Debug, Clone)]
+pub struct LocalSdConfig {
+    /// 服务地址，`mock` 表示使用内置的模拟服务器
+    pub base_url: String,
+    pub api: LocalSdApi,
+    /// `default` 表示使用服务端当前加载的模型，其余值作为 checkpoint 名称传递
+    pub model: String,
+    pub supported_models: Vec<String>,
+    pub steps: u32,
+    pub width: u32,
+    pub height: u32,
+    /// img2img 的重绘幅度
+    pub denoising_strength: f32,
+    /// 自定义 ComfyUI 文生图工作流 JSON 文件路径
+    pub workflow_path: Option<String>,
+    /// 自定义 ComfyUI 图生图工作流 JSON 文件路径
+    pub edit_workflow_path: Option<String>,
+}
+
+impl LocalSdConfig {
+    /// 仅在提供了本地服务地址时启用该后端
+    fn from_env(args: &[String]) -> Result<Option<Self>> {
+        let Some(base_url) = OpenRouterConfig::get_arg_value(args, "--local-sd-url")
+            .or_else(|| env::var("LOCAL_SD_URL").ok())
+        else {
+            return Ok(None);
+        };
+
+        let api = match env::var("LOCAL_SD_API")
+            .unwrap_or_else(|_| "a1111".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "a1111" | "automatic1111" => LocalSdApi::Automatic1111,
+            "comfyui" | "comfy" => LocalSdApi::ComfyUi,
+            other => return Err(anyhow!("不支持的本地 API 类型: {}。可选值: a1111, comfyui", other)),
+        };
+
+        let model = env::var("LOCAL_SD_MODEL").unwrap_or_else(|_| "default".to_string());
+
+        let supported_models = env::var("LOCAL_SD_SUPPORTED_MODELS")
+            .ok()
+            .map(|list| OpenRouterConfig::parse_model_list(&list))
+            .filter(|models| !models.is_empty())
+            .unwrap_or_else(|| vec![model.clone()]);
+
+        if !supported_models.contains(&model) {
+            return Err(anyhow!("不支持的本地模型: {}。支持的模型: {}",
+                model,
+                supported_models.join(", ")));
+        }
+
+        let steps = parse_number(args, None, "LOCAL_SD_STEPS", 20, positive)?;
+
+        let (width, height) = match env::var("LOCAL_SD_SIZE") {
+            Ok(size) => size
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+                .ok_or_else(|| anyhow!("LOCAL_SD_SIZE 格式无效: {}，应为 宽x高，例如 512x512", size))?,
+            Err(_) => (512, 512),
+        };
+
+        let denoising_strength = parse_number(args, None, "LOCAL_SD_DENOISING_STRENGTH", 0.75, |strength: f32| {
+            if (0.0..=1.0).contains(&strength) {
+                Ok(strength)
+            } else {
+                Err("必须在 0.0 到 1.0 之间".to_string())
+            }
+        })?;
+
+        let workflow_path = env::var("LOCAL_SD_WORKFLOW").ok();
+        let edit_workflow_path = env::var("LOCAL_SD_EDIT_WORKFLOW").ok();
+
+        Ok(Some(Self {
+            base_url,
+            api,
+            model,
+            supported_models,
+            steps,
+            width,
+            height,
+            denoising_strength,
+            workflow_path,
+            edit_workflow_path,
+        }))
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OpenRouterModel {
     pub id: String,
@@ -121,12 +716,41 @@ pub struct OpenRouterModel {
     pub context_length: Option<u32>,
     pub pricing: Option<ModelPricing>,
     pub top_provider: Option<ModelProvider>,
+    pub architecture: Option<ModelArchitecture>,
+}
+
+impl OpenRouterModel {
+    /// 模型是否能输出图像
+    pub fn supports_image_output(&self) -> bool {
+        let Some(architecture) = &self.architecture else {
+            return false;
+        };
+
+        if let Some(output_modalities) = &architecture.output_modalities {
+            return output_modalities.iter().any(|m| m == "image");
+        }
+
+        // 旧版响应只有 "text+image->text+image" 形式的 modality 字段
+        architecture.modality
+            .as_deref()
+            .and_then(|modality| modality.split("->").nth(1))
+            .map(|output| output.split('+').any(|m| m == "image"))
+            .unwrap_or(false)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ModelPricing {
     pub prompt: String,
     pub completion: String,
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModelArchitecture {
+    pub modality: Option<String>,
+    pub input_modalities: Option<Vec<String>>,
+    pub output_modalities: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -147,84 +771,105 @@ pub struct ChatMessage {
     pub content: serde_json::Value, // 可以是字符串或数组（多模态）
 }
 
+/// 多模态消息内容中的一项
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ChatRequest {
     pub model: String,
     pub messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ChatResponse {
+    #[serde(default)]
     pub choices: Vec<ChatChoice>,
     pub usage: Option<ChatUsage>,
+    /// 上游提供商的错误可能以 200 状态码返回
+    pub error: Option<ChatError>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ChatChoice {
-    pub message: ChatMessage,
+    pub message: ChatMessageWithImages,
     pub finish_reason: Option<String>,
+    pub error: Option<ChatError>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatUsage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
+/// OpenRouter 响应中的错误对象
+#[derive(Debug, Deserialize)]
+pub struct ChatError {
+    /// 通常与 HTTP 状态码一致，部分提供商返回字符串
+    pub code: Option<serde_json::Value>,
+    pub message: String,
+    pub metadata: Option<serde_json::Value>,
+}
+
+impl ChatError {
+    /// 数字形式的错误码
+    pub fn status(&self) -> Option<u16> {
+        match &self.code {
+            Some(serde_json::Value::Number(code)) => code.as_u64().map(|c| c as u16),
+            Some(serde_json::Value::String(code)) => code.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
 // 新增：图像相关结构体
 #[derive(Debug, Deserialize)]
 pub struct ImageResponse {
-    pub url: String,
-    pub detail: Option<String>,
+    pub image_url: ImageUrl,
 }
 
+/// 图像模型返回的消息：文本内容可能为空，生成的图像在 `images` 数组中
 #[derive(Debug, Deserialize)]
 pub struct ChatMessageWithImages {
-    pub content: String,
+    pub content: Option<String>,
     pub images: Option<Vec<ImageResponse>>,
 }
 
-// 新增：工具请求参数结构体
-#[derive(Debug, Deserialize)]
-pub struct ChatWithModelParams {
-    pub model: String,
-    pub message: serde_json::Value, // 可以是字符串或数组
-    pub max_tokens: Option<u32>,
-    pub temperature: Option<f32>,
-    pub system_prompt: Option<String>,
-    pub save_directory: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct CompareModelsParams {
+    /// 参与对比的模型 ID，必须都在支持列表中
+    #[schemars(example = &["google/gemini-2.5-flash-image-preview:free", "google/gemini-2.5-flash-image-preview"])]
     pub models: Vec<String>,
+    /// 发送给每个模型的提示词：字符串或多模态内容数组
+    #[schemars(example = &"一只可爱的小猫穿着宇航服在月球上行走，科幻风格")]
     pub message: serde_json::Value,
     pub max_tokens: Option<u32>,
+    /// 使用的后端名称（默认使用服务器配置的后端）
+    pub backend: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct GenerateImageParams {
-    pub model: Option<String>,
-    pub prompt: String,
-    pub max_tokens: Option<u32>,
-    pub temperature: Option<f32>,
-    pub save_directory: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct EditImageParams {
-    pub model: Option<String>,
-    pub instruction: String,
-    pub images: Vec<String>,
-    pub max_tokens: Option<u32>,
-    pub temperature: Option<f32>,
-    pub save_directory: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct GetModelInfoParams {
+    /// 模型 ID
+    #[schemars(example = &"google/gemini-2.5-flash-image-preview")]
     pub model: String,
+    /// 查询的后端名称（默认使用服务器配置的后端）
+    pub backend: Option<String>,
 } 
\ No newline at end of file
@@ -0,0 +1,254 @@
+use rmcp::model::ErrorCode;
+use rmcp::ErrorData as McpError;
+use serde_json::{json, Value};
+
+use crate::backends::retry;
+
+/// 工具调用失败的原因，转换为 McpError 时映射到对应的 MCP 错误码
+///
+/// 错误数据中始终包含 `kind`、`status`（上游 HTTP 状态码，没有时为 null）和 `retryable`，
+/// 调用方可以据此判断是修正参数、稍后重试还是放弃；`model` 在已知时由调用管道补充。
+#[derive(Debug, thiserror::Error)]
+pub enum ImageError {
+    /// 调用参数无效，例如空的图像列表或超出范围的 temperature
+    #[error("{0}")]
+    InvalidParams(String),
+
+    /// 模型不在后端的允许列表中
+    #[error("后端 {backend} 不支持模型: {model}。支持的模型: {}", .supported.join(", "))]
+    UnsupportedModel {
+        backend: String,
+        model: String,
+        supported: Vec<String>,
+    },
+
+    /// 后端上不存在该模型
+    #[error("后端 {backend} 上找不到模型: {model}")]
+    ModelNotFound { backend: String, model: String },
+
+    /// 未配置的后端
+    #[error("不支持的后端: {name}。可用后端: {}", .available.join(", "))]
+    UnknownBackend { name: String, available: Vec<String> },
+
+    /// 输入图像无法读取、解码或不被后端支持
+    #[error("{0}")]
+    InvalidImage(String),
+
+    /// URL 输入被拒绝：协议不支持、域名不在允许列表中或解析到内网地址
+    #[error("不允许使用图像 URL {url}: {reason}")]
+    UrlNotAllowed { url: String, reason: String },
+
+    /// 本地输入图像不存在或不在允许的根目录中；两种情况不作区分，避免泄露文件是否存在
+    #[error("不允许读取输入图像 {path}：文件不存在或不在允许的输入目录中 ({})（可通过 MCP_INPUT_ROOTS 或 --input-roots 配置）", .roots.join(", "))]
+    PathNotAllowed { path: String, roots: Vec<String> },
+
+    /// 上游拒绝了凭据 (401/403)
+    #[error("{0}")]
+    Unauthorized(UpstreamError),
+
+    /// 上游限流 (429)
+    #[error("{0}")]
+    RateLimited(UpstreamError),
+
+    /// 内容审核拒绝了提示词或图像
+    #[error("{0}")]
+    ContentRejected(UpstreamError),
+
+    /// 其他上游错误
+    #[error("{0}")]
+    Upstream(UpstreamError),
+
+    /// 连接失败或超时
+    #[error("{message}")]
+    Network {
+        message: String,
+        /// 超时类型："connect" 或 "request"
+        timeout: Option<&'static str>,
+        retryable: bool,
+    },
+
+    /// 上游响应无法解析或缺少必要内容
+    #[error("{message}")]
+    InvalidResponse {
+        message: String,
+        /// 响应体片段
+        body: Option<String>,
+    },
+
+    /// 服务器内部错误
+    #[error("{0}")]
+    Internal(String),
+}
+
+/// 上游服务返回的错误：HTTP 状态码错误或响应体中的 error 对象
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub service: String,
+    pub status: Option<u16>,
+    pub message: String,
+    pub retry_after_ms: Option<u64>,
+    /// 错误来自响应体中的 error 对象（上游提供商的错误可能以 200 状态码返回）
+    pub provider_error: bool,
+    /// 上游 error 对象中的 metadata，例如审核原因
+    pub metadata: Option<Value>,
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.provider_error {
+            return write!(f, "{} 返回错误: {}", self.service, self.message);
+        }
+        let status = self.status
+            .and_then(|s| reqwest::StatusCode::from_u16(s).ok())
+            .map_or_else(|| "未知".to_string(), |s| s.to_string());
+        write!(f, "{} 请求失败，状态码: {}, 错误: {}", self.service, status, self.message)
+    }
+}
+
+impl ImageError {
+    /// 按状态码和错误元数据对上游错误分类
+    pub fn upstream(error: UpstreamError) -> Self {
+        if error.metadata.as_ref().is_some_and(is_moderation) {
+            return Self::ContentRejected(error);
+        }
+        match error.status {
+            Some(401) | Some(403) => Self::Unauthorized(error),
+            Some(429) => Self::RateLimited(error),
+            _ => Self::Upstream(error),
+        }
+    }
+
+    /// 错误数据中的 `kind`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidParams(_) => "invalid_params",
+            Self::UnsupportedModel { .. } => "unsupported_model",
+            Self::ModelNotFound { .. } => "model_not_found",
+            Self::UnknownBackend { .. } => "unknown_backend",
+            Self::InvalidImage(_) => "invalid_image",
+            Self::PathNotAllowed { .. } => "path_not_allowed",
+            Self::UrlNotAllowed { .. } => "url_not_allowed",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::RateLimited(_) => "rate_limited",
+            Self::ContentRejected(_) => "content_rejected",
+            Self::Upstream(_) => "upstream",
+            Self::Network { .. } => "network",
+            Self::InvalidResponse { .. } => "invalid_response",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    fn upstream_error(&self) -> Option<&UpstreamError> {
+        match self {
+            Self::Unauthorized(e) | Self::RateLimited(e) | Self::ContentRejected(e) | Self::Upstream(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// 调用方修正参数可以解决的错误映射为 invalid_params，上游 404 映射为 resource_not_found
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::InvalidParams(_)
+            | Self::UnsupportedModel { .. }
+            | Self::UnknownBackend { .. }
+            | Self::InvalidImage(_)
+            | Self::PathNotAllowed { .. }
+            | Self::UrlNotAllowed { .. }
+            | Self::ContentRejected(_) => ErrorCode::INVALID_PARAMS,
+            Self::ModelNotFound { .. } => ErrorCode::RESOURCE_NOT_FOUND,
+            Self::Upstream(e) => match e.status {
+                Some(400) | Some(422) => ErrorCode::INVALID_PARAMS,
+                Some(404) => ErrorCode::RESOURCE_NOT_FOUND,
+                _ => ErrorCode::INTERNAL_ERROR,
+            },
+            _ => ErrorCode::INTERNAL_ERROR,
+        }
+    }
+
+    /// 稍后重试是否可能成功
+    pub fn retryable(&self) -> bool {
+        match self {
+            Self::RateLimited(_) => true,
+            Self::Upstream(e) => e.status.is_some_and(retry::is_retryable_status),
+            Self::Network { retryable, .. } => *retryable,
+            _ => false,
+        }
+    }
+}
+
+impl From<ImageError> for McpError {
+    fn from(error: ImageError) -> Self {
+        let mut data = json!({
+            "kind": error.kind(),
+            "status": error.upstream_error().and_then(|e| e.status),
+            "retryable": error.retryable(),
+        });
+        if let Some(upstream) = error.upstream_error() {
+            data["service"] = json!(upstream.service);
+            data["provider_error"] = json!(upstream.provider_error);
+            if let Some(retry_after_ms) = upstream.retry_after_ms {
+                data["retry_after_ms"] = json!(retry_after_ms);
+            }
+            if let Some(metadata) = &upstream.metadata {
+                data["metadata"] = metadata.clone();
+            }
+        }
+        match &error {
+            ImageError::UnsupportedModel { backend, model, supported } => {
+                data["backend"] = json!(backend);
+                data["model"] = json!(model);
+                data["supported_models"] = json!(supported);
+            }
+            ImageError::ModelNotFound { backend, model } => {
+                data["backend"] = json!(backend);
+                data["model"] = json!(model);
+            }
+            ImageError::UnknownBackend { name, available } => {
+                data["backend"] = json!(name);
+                data["available_backends"] = json!(available);
+            }
+            ImageError::PathNotAllowed { path, roots } => {
+                data["path"] = json!(path);
+                data["allowed_roots"] = json!(roots);
+            }
+            ImageError::UrlNotAllowed { url, .. } => {
+                data["url"] = json!(url);
+            }
+            ImageError::Network { timeout, .. } => {
+                data["timeout"] = json!(timeout);
+            }
+            ImageError::InvalidResponse { body: Some(body), .. } => {
+                data["body"] = json!(body);
+            }
+            _ => {}
+        }
+
+        McpError::new(error.code(), error.to_string(), Some(data))
+    }
+}
+
+/// OpenRouter 的审核错误在 metadata 中带有 reasons 和 flagged_input
+fn is_moderation(metadata: &Value) -> bool {
+    metadata.get("reasons").is_some() || metadata.get("flagged_input").is_some()
+}
+
+/// 向错误数据中写入一个字段；错误没有数据时新建
+pub fn insert_data(error: &mut McpError, key: &str, value: Value) {
+    match error.data.as_mut().and_then(Value::as_object_mut) {
+        Some(data) => {
+            data.insert(key.to_string(), value);
+        }
+        None => error.data = Some(json!({ key: value })),
+    }
+}
+
+/// 在错误数据中补充失败的模型，已有 model 字段时保留原值
+pub fn with_model(mut error: McpError, model: &str) -> McpError {
+    let has_model = error.data.as_ref()
+        .and_then(|data| data.get("model"))
+        .is_some_and(|model| !model.is_null());
+    if !has_model {
+        insert_data(&mut error, "model", json!(model));
+    }
+    error
+}
@@ -1,9 +1,19 @@
 use std::fs;
-use std::path::Path;
-use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::io::{Cursor, Read, Write};
 use base64::{Engine as _, engine::general_purpose};
 use anyhow::{Result, anyhow};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use image::{DynamicImage, GenericImageView, imageops::FilterType};
+
+use crate::config::{InputImageConfig, ReencodeFormat};
+use crate::error::ImageError;
+
+/// 缩放前解码的图像宽高上限
+const MAX_DECODE_DIMENSION: u32 = 16384;
+
+/// 解码单张图像时允许分配的最大内存
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
 
 /// 生成递增的文件名，避免重复
 fn generate_incremental_filename(base_name: &str, extension: &str, directory: &str) -> String {
@@ -84,41 +94,40 @@ pub fn save_base64_image(base64_data: &str, directory: &str, filename: Option<&s
     Ok(filepath.to_string_lossy().to_string())
 }
 
-/// 保存OpenRouter API的响应图像，支持递增文件名
+/// 保存后端返回的图像（data URL 或 http(s) URL），支持递增文件名
 pub fn save_response_images(
-    images: &[serde_json::Value], 
+    images: &[String], 
     save_directory: Option<&str>,
     base_filename: Option<&str>,
     is_edit: bool
 ) -> Vec<ImageInfo> {
     if let Some(dir) = save_directory {
         if let Ok(_dir_path) = fs::canonicalize(dir) {
-            return images.iter().enumerate().map(|(index, img)| {
-                let image_url = img.get("image_url")
-                    .and_then(|url_obj| url_obj.get("url"))
-                    .and_then(|url| url.as_str())
-                    .unwrap_or("");
-
+            return images.iter().enumerate().map(|(index, image_url)| {
                 let mut image_info = ImageInfo {
                     url: image_url.to_string(),
                     saved_path: None,
                 };
 
                 if image_url.starts_with("data:image/") {
+                    // 扩展名与图像类型一致，资源读取时按扩展名得到的 MIME 类型才正确
+                    let extension = split_data_url(image_url)
+                        .map(|(mime_type, _)| extension_for_mime(mime_type))
+                        .unwrap_or("png");
                     // 生成递增的文件名
                     let filename = if let Some(base_name) = base_filename {
                         if is_edit {
                             // 编辑模式：保留原文件名，添加 "edited" 标记
                             let base_with_edited = format!("{}_edited", base_name);
-                            generate_incremental_filename(&base_with_edited, "png", dir)
+                            generate_incremental_filename(&base_with_edited, extension, dir)
                         } else {
                             // 生成模式：使用基础名称
-                            generate_incremental_filename(base_name, "png", dir)
+                            generate_incremental_filename(base_name, extension, dir)
                         }
                     } else {
                         // 默认文件名
                         let default_name = if is_edit { "edited_image" } else { "generated_image" };
-                        generate_incremental_filename(&format!("{}_{}", default_name, index + 1), "png", dir)
+                        generate_incremental_filename(&format!("{}_{}", default_name, index + 1), extension, dir)
                     };
 
                     match save_base64_image(image_url, dir, Some(&filename)) {
@@ -126,7 +135,7 @@ pub fn save_response_images(
                             image_info.saved_path = Some(saved_path);
                         }
                         Err(e) => {
-                            eprintln!("保存图像 {} 失败: {}", index + 1, e);
+                            tracing::warn!(index = index + 1, error = %e, "保存图像失败");
                         }
                     }
                 }
@@ -137,12 +146,7 @@ pub fn save_response_images(
     }
 
     // 如果没有指定保存目录，只返回URL信息
-    images.iter().map(|img| {
-        let image_url = img.get("image_url")
-            .and_then(|url_obj| url_obj.get("url"))
-            .and_then(|url| url.as_str())
-            .unwrap_or("");
-        
+    images.iter().map(|image_url| {
         ImageInfo {
             url: image_url.to_string(),
             saved_path: None,
@@ -154,17 +158,33 @@ pub fn save_response_images(
 pub struct ImageInfo {
     pub url: String,
     pub saved_path: Option<String>,
-} 
+}
+
+impl ImageInfo {
+    /// 如果图像是 base64 data URL，返回 (MIME 类型, 纯 base64 数据)
+    pub fn inline_data(&self) -> Option<(&str, &str)> {
+        split_data_url(&self.url)
+    }
+}
+
+/// 将 `data:image/png;base64,xxx` 拆分为 ("image/png", "xxx")
+pub fn split_data_url(data_url: &str) -> Option<(&str, &str)> {
+    let rest = data_url.strip_prefix("data:")?;
+    let (mime_type, data) = rest.split_once(";base64,")?;
+    if !mime_type.starts_with("image/") || data.is_empty() {
+        return None;
+    }
+    Some((mime_type, data))
+}
 
 /// 检测图片输入类型并返回标准化的内容格式
-pub fn detect_and_process_image_input(image_input: &str) -> Result<ImageContent> {
+///
+/// 本地文件只有位于 `input_roots` 中某个目录下时才会读取，符号链接和 ".." 会先解析为规范路径再检查。
+/// 本地文件和 data URL 按 `limits` 检查大小，启用缩放时在发送前缩小并重新编码。
+pub fn detect_and_process_image_input(image_input: &str, input_roots: &[PathBuf], limits: &InputImageConfig) -> Result<ImageContent> {
     // 检测是否为 base64 数据
     if image_input.starts_with("data:image/") {
-        return Ok(ImageContent {
-            content_type: "base64".to_string(),
-            data: image_input.to_string(),
-            mime_type: extract_mime_type_from_base64(image_input)?,
-        });
+        return process_data_url(image_input, limits);
     }
     
     // 检测是否为 URL
@@ -172,92 +192,287 @@ pub fn detect_and_process_image_input(image_input: &str) -> Result<ImageContent>
         return Ok(ImageContent {
             content_type: "url".to_string(),
             data: image_input.to_string(),
-            mime_type: "image/*".to_string(),
-        });
-    }
-    
-    // 检测是否为本地文件路径
-    let path = Path::new(image_input);
-    if path.exists() && path.is_file() {
-        // 读取文件并转换为 base64
-        let file_bytes = fs::read(image_input)?;
-        let mime_type = detect_mime_type_from_path(path)?;
-        let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-        let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-        
-        return Ok(ImageContent {
-            content_type: "base64".to_string(),
-            data: data_url,
-            mime_type,
-        });
-    }
-    
-    // 如果都不是，尝试作为相对路径处理
-    let current_dir = std::env::current_dir()?;
-    let full_path = current_dir.join(image_input);
-    if full_path.exists() && full_path.is_file() {
-        let file_bytes = fs::read(&full_path)?;
-        let mime_type = detect_mime_type_from_path(&full_path)?;
-        let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-        let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-        
-        return Ok(ImageContent {
-            content_type: "base64".to_string(),
-            data: data_url,
-            mime_type,
+            warning: None,
+            note: None,
         });
     }
     
-    // 如果仍然找不到，尝试在 save_directory 中查找
+    // 本地文件路径：相对路径按当前目录解析，其次在 MCP_SAVE_DIRECTORY 中查找
+    let mut candidates = vec![PathBuf::from(image_input)];
     if let Ok(save_dir) = std::env::var("MCP_SAVE_DIRECTORY") {
-        let save_path = Path::new(&save_dir).join(image_input);
-        if save_path.exists() && save_path.is_file() {
-            let file_bytes = fs::read(&save_path)?;
-            let mime_type = detect_mime_type_from_path(&save_path)?;
-            let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-            let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-            
-            return Ok(ImageContent {
-                content_type: "base64".to_string(),
-                data: data_url,
-                mime_type,
-            });
-        }
+        candidates.push(Path::new(&save_dir).join(image_input));
     }
-    
-    Err(anyhow!("无法识别的图片输入格式: {}", image_input))
+    let canonical = resolve_local_file(image_input, &candidates, input_roots)?;
+    read_local_image(image_input, &canonical, limits)
 }
 
 /// 在指定的保存目录中查找图片文件
-pub fn find_image_in_save_directory(image_input: &str, save_directory: &str) -> Result<ImageContent> {
-    let save_path = Path::new(save_directory).join(image_input);
-    if save_path.exists() && save_path.is_file() {
-        let file_bytes = fs::read(&save_path)?;
-        let mime_type = detect_mime_type_from_path(&save_path)?;
-        let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-        let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-        
+pub fn find_image_in_save_directory(image_input: &str, save_directory: &str, input_roots: &[PathBuf], limits: &InputImageConfig) -> Result<ImageContent> {
+    let candidates = [Path::new(save_directory).join(image_input)];
+    let canonical = resolve_local_file(image_input, &candidates, input_roots)?;
+    read_local_image(image_input, &canonical, limits)
+}
+
+/// 返回候选路径中第一个位于允许根目录中的文件的规范路径
+///
+/// 先规范化并检查根目录，再检查是否为文件；文件不存在和不在根目录中返回同一个错误，不泄露文件是否存在
+fn resolve_local_file(image_input: &str, candidates: &[PathBuf], input_roots: &[PathBuf]) -> Result<PathBuf> {
+    candidates
+        .iter()
+        .filter_map(|candidate| ensure_within_roots(candidate, input_roots).ok())
+        .find(|canonical| canonical.is_file())
+        .ok_or_else(|| path_not_allowed(Path::new(image_input), input_roots).into())
+}
+
+/// 检查本地路径是否位于允许的根目录中，返回解析符号链接后的规范路径
+///
+/// 路径不存在或不在根目录中时都返回 `ImageError::PathNotAllowed`，调用方可以通过 downcast 区分
+pub fn ensure_within_roots(path: &Path, input_roots: &[PathBuf]) -> Result<PathBuf> {
+    // 根目录同样需要规范化，不存在的根目录直接忽略；按路径组件比较，/data2 不在 /data 中
+    path.canonicalize()
+        .ok()
+        .filter(|canonical| input_roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| canonical.starts_with(root)))
+        .ok_or_else(|| path_not_allowed(path, input_roots).into())
+}
+
+fn path_not_allowed(path: &Path, input_roots: &[PathBuf]) -> ImageError {
+    ImageError::PathNotAllowed {
+        path: path.display().to_string(),
+        roots: input_roots.iter().map(|root| root.display().to_string()).collect(),
+    }
+}
+
+/// 读取允许范围内的本地图片文件并转换为 base64 data URL
+///
+/// MIME 类型按文件内容识别，不是图像的文件返回 `ImageError::InvalidImage`；扩展名与内容不符时附带警告。
+/// 超过读取上限的文件在读取前拒绝，读取时也不会超过上限。
+fn read_local_image(image_input: &str, canonical: &Path, limits: &InputImageConfig) -> Result<ImageContent> {
+    let source = image_input.to_string();
+    ensure_source_size(&source, fs::metadata(canonical)?.len(), limits)?;
+    // 文件可能在检查后变大，读取时同样限制长度
+    let mut file_bytes = Vec::new();
+    fs::File::open(canonical)?
+        .take(limits.source_limit() + 1)
+        .read_to_end(&mut file_bytes)?;
+    ensure_source_size(&source, file_bytes.len() as u64, limits)?;
+    let mime_type = sniff_image_mime(&file_bytes).ok_or_else(|| ImageError::InvalidImage(
+        format!("文件 {} 不是支持的图像格式 (PNG/JPEG/GIF/WebP/BMP/TIFF)", image_input),
+    ))?;
+
+    let extension_mime = detect_mime_type_from_path(canonical).ok();
+    let warning = (extension_mime.as_deref() != Some(mime_type)).then(|| format!(
+        "文件 {} 的扩展名与内容不符 (扩展名: {}，内容: {})，已按 {} 处理",
+        image_input,
+        extension_mime.as_deref().unwrap_or("无"),
+        mime_type,
+        mime_type,
+    ));
+
+    let (mime_type, file_bytes, note) = prepare_image(file_bytes, mime_type, &source, limits)?;
+    let base64_data = general_purpose::STANDARD.encode(&file_bytes);
+    let data_url = format!("data:{};base64,{}", mime_type, base64_data);
+
+    Ok(ImageContent {
+        content_type: "base64".to_string(),
+        data: data_url,
+        warning,
+        note,
+    })
+}
+
+/// 按大小上限检查 data URL 图像，启用缩放时解码后缩小并重新编码
+///
+/// 服务器下载的 URL 图像同样经过这里处理
+pub fn process_data_url(data_url: &str, limits: &InputImageConfig) -> Result<ImageContent> {
+    let (mime_type, data) = split_data_url(data_url)
+        .ok_or_else(|| ImageError::InvalidImage("无效的 base64 图像数据格式".to_string()))?;
+    let source = format!("{} data URL", mime_type);
+
+    // 按 base64 长度计算大小，超过上限时不解码；不缩放时也不需要解码
+    ensure_source_size(&source, base64_decoded_len(data), limits)?;
+    if limits.max_dimension.is_none() {
         return Ok(ImageContent {
             content_type: "base64".to_string(),
-            data: data_url,
-            mime_type,
+            data: data_url.to_string(),
+            warning: None,
+            note: None,
         });
     }
-    
-    Err(anyhow!("在保存目录 '{}' 中找不到图片文件: {}", save_directory, image_input))
+
+    let bytes = general_purpose::STANDARD.decode(data)
+        .map_err(|e| ImageError::InvalidImage(format!("base64 解码失败: {}", e)))?;
+    let (mime_type, bytes, note) = prepare_image(bytes, mime_type, &source, limits)?;
+    let data_url = match note {
+        Some(_) => format!("data:{};base64,{}", mime_type, general_purpose::STANDARD.encode(&bytes)),
+        None => data_url.to_string(),
+    };
+
+    Ok(ImageContent {
+        content_type: "base64".to_string(),
+        data: data_url,
+        warning: None,
+        note,
+    })
 }
 
-/// 从 base64 数据中提取 MIME 类型
-fn extract_mime_type_from_base64(base64_data: &str) -> Result<String> {
-    let mime_part = base64_data
-        .split(";base64,")
-        .next()
-        .ok_or_else(|| anyhow!("无效的base64数据格式"))?;
-    
-    if mime_part.starts_with("data:image/") {
-        Ok(mime_part.to_string())
+/// data URL 中图像的字节数，不是 data URL 时返回 None
+pub fn data_url_size(data_url: &str) -> Option<u64> {
+    split_data_url(data_url).map(|(_, data)| base64_decoded_len(data))
+}
+
+/// 把一张输入图像的大小计入本次调用的总大小，超过总上限时返回错误
+///
+/// URL 输入不计入，只有发送给后端的内联图像占用请求体
+pub fn add_to_total_size(total_bytes: &mut u64, image: &str, limits: &InputImageConfig) -> Result<(), ImageError> {
+    *total_bytes += data_url_size(image).unwrap_or(0);
+    if *total_bytes > limits.max_total_bytes {
+        return Err(ImageError::InvalidImage(format!(
+            "输入图像总大小超过上限 {} 字节（可通过 MCP_MAX_TOTAL_IMAGE_BYTES 调整，或设置 MCP_DOWNSCALE_MAX_DIMENSION 自动缩小）",
+            limits.max_total_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// 不解码计算 base64 数据解码后的字节数
+fn base64_decoded_len(data: &str) -> u64 {
+    let padding = data.bytes().rev().take_while(|&b| b == b'=').count();
+    (data.len() / 4 * 3).saturating_sub(padding) as u64
+}
+
+/// 检查读取和解码前的源图像大小：不缩放时使用单张上限，缩放时使用缩放前的源图像上限
+fn ensure_source_size(source: &str, size: u64, limits: &InputImageConfig) -> Result<(), ImageError> {
+    if limits.max_dimension.is_none() {
+        return ensure_image_size(source, size, limits);
+    }
+    if size > limits.source_limit() {
+        return Err(ImageError::InvalidImage(format!(
+            "输入图像 {} 的大小 {} 字节超过缩放前的上限 {} 字节（可通过 MCP_MAX_SOURCE_IMAGE_BYTES 调整）",
+            source, size, limits.source_limit()
+        )));
+    }
+    Ok(())
+}
+
+fn ensure_image_size(source: &str, size: u64, limits: &InputImageConfig) -> Result<(), ImageError> {
+    if size > limits.max_image_bytes {
+        return Err(ImageError::InvalidImage(format!(
+            "输入图像 {} 的大小 {} 字节超过单张上限 {} 字节（可通过 MCP_MAX_IMAGE_BYTES 调整，或设置 MCP_DOWNSCALE_MAX_DIMENSION 自动缩小）",
+            source, size, limits.max_image_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// 启用缩放时，把最长边或大小超过上限的图像缩小并重新编码
+///
+/// 返回 (MIME 类型, 图像字节, 处理说明)，说明中包含原始尺寸；不需要处理时原样返回
+fn prepare_image(bytes: Vec<u8>, mime_type: &str, source: &str, limits: &InputImageConfig) -> Result<(String, Vec<u8>, Option<String>)> {
+    let Some(max_dimension) = limits.max_dimension else {
+        ensure_image_size(source, bytes.len() as u64, limits)?;
+        return Ok((mime_type.to_string(), bytes, None));
+    };
+
+    let image = decode_limited(&bytes)
+        .map_err(|e| ImageError::InvalidImage(format!("无法解码输入图像 {}: {}", source, e)))?;
+    let (width, height) = image.dimensions();
+    let oversized = width.max(height) > max_dimension;
+    if !oversized && bytes.len() as u64 <= limits.max_image_bytes {
+        return Ok((mime_type.to_string(), bytes, None));
+    }
+
+    // resize 保持宽高比，两边都不超过上限
+    let image = if oversized {
+        image.resize(max_dimension, max_dimension, FilterType::Lanczos3)
     } else {
-        Err(anyhow!("无效的图像MIME类型: {}", mime_part))
+        image
+    };
+    let encoded = encode_image(&image, limits)?;
+    ensure_image_size(source, encoded.len() as u64, limits)?;
+
+    let target_mime = limits.format.mime_type();
+    let note = format!(
+        "{}: 原始尺寸 {}x{} ({}，{} 字节)，已{}为 {}x{} ({}，{} 字节)",
+        source,
+        width,
+        height,
+        mime_type,
+        bytes.len(),
+        if oversized { "缩小并重新编码" } else { "重新编码" },
+        image.width(),
+        image.height(),
+        target_mime,
+        encoded.len(),
+    );
+    Ok((target_mime.to_string(), encoded, Some(note)))
+}
+
+/// 限制尺寸和内存分配后解码，防止解压炸弹耗尽内存
+fn decode_limited(bytes: &[u8]) -> image::ImageResult<DynamicImage> {
+    let mut decode_limits = image::Limits::default();
+    decode_limits.max_image_width = Some(MAX_DECODE_DIMENSION);
+    decode_limits.max_image_height = Some(MAX_DECODE_DIMENSION);
+    decode_limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = image::ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(decode_limits);
+    reader.decode()
+}
+
+/// 按配置的格式编码图像；JPEG 不支持透明通道，先转换为 RGB
+fn encode_image(image: &DynamicImage, limits: &InputImageConfig) -> Result<Vec<u8>, ImageError> {
+    let mut buffer = Cursor::new(Vec::new());
+    let result = match limits.format {
+        ReencodeFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, limits.quality);
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)
+        }
+        ReencodeFormat::Webp => {
+            let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut buffer);
+            DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder)
+        }
+        ReencodeFormat::Png => image.write_to(&mut buffer, image::ImageFormat::Png),
+    };
+    result.map_err(|e| ImageError::Internal(format!("重新编码输入图像失败: {}", e)))?;
+    Ok(buffer.into_inner())
+}
+
+/// 根据文件头的魔数识别图像类型，支持 PNG、JPEG、GIF、WebP、BMP 和 TIFF
+pub fn sniff_image_mime(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        // "BM" 过短，额外要求完整的 BMP 文件头和信息头
+        [b'B', b'M', ..] if bytes.len() >= 26 => Some("image/bmp"),
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Some("image/tiff"),
+        _ => None,
+    }
+}
+
+/// 保存图像时使用的文件扩展名，未知类型按 PNG 命名
+fn extension_for_mime(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tiff",
+        _ => "png",
+    }
+}
+
+/// 保存目录中文件的 MIME 类型：优先按文件头识别，识别不了时（如 SVG）按扩展名判断
+///
+/// 扩展名与内容不符的文件（例如旧版本把 JPEG 保存为 .png）也能得到正确的类型
+fn saved_file_mime(path: &Path, header: &[u8]) -> Option<String> {
+    match sniff_image_mime(header) {
+        Some(mime_type) => Some(mime_type.to_string()),
+        None => detect_mime_type_from_path(path).ok().filter(|mime_type| mime_type != "image/*"),
     }
 }
 
@@ -287,5 +502,402 @@ fn detect_mime_type_from_path(file_path: &Path) -> Result<String> {
 pub struct ImageContent {
     pub content_type: String,  // "url", "base64", "file"
     pub data: String,          // 实际的数据内容
-    pub mime_type: String,     // MIME 类型
-} 
\ No newline at end of file
+    pub warning: Option<String>, // 需要提示调用方的问题，例如扩展名与内容不符
+    pub note: Option<String>,    // 发送前对图像做的处理，例如缩放前的原始尺寸
+} 
+
+/// 保存目录中的图像文件条目
+#[derive(Debug)]
+pub struct SavedImageEntry {
+    pub filename: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub created: Option<DateTime<Utc>>,
+}
+
+/// 列出保存目录中的所有图像文件，按文件名排序
+pub fn list_saved_images(save_directory: &str) -> Result<Vec<SavedImageEntry>> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(save_directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        // 只收录文件头或扩展名可识别的图像文件
+        let mut header = Vec::new();
+        if fs::File::open(&path).and_then(|file| file.take(32).read_to_end(&mut header)).is_err() {
+            continue;
+        }
+        let Some(mime_type) = saved_file_mime(&path, &header) else {
+            continue;
+        };
+
+        let Some(filename) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        // 部分文件系统不支持创建时间，退回到修改时间
+        let created = metadata.created()
+            .or_else(|_| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+
+        entries.push(SavedImageEntry {
+            filename: filename.to_string(),
+            mime_type,
+            size: metadata.len(),
+            created,
+        });
+    }
+
+    entries.sort_by(|a, b| a.filename.cmp(&b.filename));
+    Ok(entries)
+}
+
+/// 读取保存目录中的图像文件，返回 (MIME 类型, 文件字节)
+pub fn read_saved_image(save_directory: &str, filename: &str) -> Result<(String, Vec<u8>)> {
+    // 只允许保存目录下的单个文件名，拒绝路径分隔符和 ".."
+    if filename.is_empty()
+        || filename.contains('/')
+        || filename.contains('\\')
+        || filename == "."
+        || filename == ".."
+    {
+        return Err(anyhow!("无效的图像文件名: {}", filename));
+    }
+
+    // 保存目录中的符号链接可能指向目录之外，解析后同样按根目录检查
+    let not_found = || anyhow!("在保存目录 '{}' 中找不到图片文件: {}", save_directory, filename);
+    let path = ensure_within_roots(&Path::new(save_directory).join(filename), &[PathBuf::from(save_directory)])
+        .map_err(|_| not_found())?;
+    if !path.is_file() {
+        return Err(not_found());
+    }
+
+    let bytes = fs::read(&path)?;
+    let mime_type = saved_file_mime(&path, &bytes)
+        .ok_or_else(|| anyhow!("保存目录中的文件 {} 不是可识别的图像", filename))?;
+    Ok((mime_type, bytes))
+}
+
+/// 稳定的 64 位 FNV-1a 哈希，不随进程或平台变化
+pub fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// 根据哈希值生成确定性的 8x8 方格占位 PNG：哈希的每一位决定一个方格是否着色
+pub fn placeholder_png(hash: u64, size: u32) -> Vec<u8> {
+    let bytes = hash.to_be_bytes();
+    // 前景色取哈希的高位字节，并保证不会太浅以便与背景区分
+    let foreground = [bytes[0] / 2 + 32, bytes[1] / 2 + 32, bytes[2] / 2 + 32];
+    let background = [0xf0, 0xf0, 0xf0];
+    let cell = (size / 8).max(1);
+
+    let image = image::RgbImage::from_fn(size, size, |x, y| {
+        let bit = (y / cell).min(7) * 8 + (x / cell).min(7);
+        image::Rgb(if hash >> bit & 1 == 1 { foreground } else { background })
+    });
+    let mut buffer = Cursor::new(Vec::new());
+    image.write_to(&mut buffer, image::ImageFormat::Png)
+        .expect("编码内存中的 PNG 不会失败");
+    buffer.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在系统临时目录下创建本测试专用的空目录
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nano-banana-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_png(path: &Path) {
+        fs::write(path, placeholder_png(1, 8)).unwrap();
+    }
+
+    fn limits() -> InputImageConfig {
+        InputImageConfig {
+            max_image_bytes: 1024 * 1024,
+            max_total_bytes: 4 * 1024 * 1024,
+            max_source_bytes: 1024 * 1024,
+            max_dimension: None,
+            format: ReencodeFormat::Png,
+            quality: 85,
+        }
+    }
+
+    fn is_path_not_allowed(result: Result<ImageContent>) -> bool {
+        matches!(result.unwrap_err().downcast::<ImageError>(), Ok(ImageError::PathNotAllowed { .. }))
+    }
+
+    /// 用 image crate 编码一张 2x2 图像，得到真实的文件头
+    fn encode_sample(format: image::ImageFormat) -> Vec<u8> {
+        encode_sized(2, 2, format)
+    }
+
+    fn encode_sized(width: u32, height: u32, format: image::ImageFormat) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image::RgbImage::new(width, height)).write_to(&mut buffer, format).unwrap();
+        buffer.into_inner()
+    }
+
+    fn png_data_url(width: u32, height: u32) -> String {
+        let png = encode_sized(width, height, image::ImageFormat::Png);
+        format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(png))
+    }
+
+    fn invalid_image_message(result: Result<ImageContent>) -> String {
+        match result.unwrap_err().downcast::<ImageError>() {
+            Ok(ImageError::InvalidImage(message)) => message,
+            other => panic!("预期 InvalidImage，实际为 {:?}", other),
+        }
+    }
+
+    #[test]
+    fn downscales_oversized_image_and_reports_original_size() {
+        let limits = InputImageConfig { max_dimension: Some(32), ..limits() };
+
+        let content = process_data_url(&png_data_url(128, 64), &limits).unwrap();
+        assert!(content.data.starts_with("data:image/png;base64,"));
+        assert!(content.note.unwrap().contains("原始尺寸 128x64"));
+        let (_, data) = split_data_url(&content.data).unwrap();
+        let image = image::load_from_memory(&general_purpose::STANDARD.decode(data).unwrap()).unwrap();
+        assert_eq!(image.dimensions(), (32, 16));
+
+        // 未超过上限的图像原样返回
+        let small = png_data_url(16, 16);
+        let content = process_data_url(&small, &limits).unwrap();
+        assert_eq!(content.data, small);
+        assert!(content.note.is_none());
+    }
+
+    #[test]
+    fn oversized_image_is_rejected_without_downscaling() {
+        let png = encode_sized(64, 64, image::ImageFormat::Png);
+        let limits = InputImageConfig { max_image_bytes: png.len() as u64 - 1, ..limits() };
+
+        let data_url = format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(&png));
+        assert!(invalid_image_message(process_data_url(&data_url, &limits)).contains("MCP_MAX_IMAGE_BYTES"));
+
+        let root = temp_dir("oversized");
+        fs::write(root.join("big.png"), &png).unwrap();
+        let input = root.join("big.png");
+        let result = detect_and_process_image_input(input.to_str().unwrap(), std::slice::from_ref(&root), &limits);
+        assert!(invalid_image_message(result).contains("MCP_MAX_IMAGE_BYTES"));
+    }
+
+    #[test]
+    fn source_over_limit_is_rejected_before_decoding() {
+        let limits = InputImageConfig { max_image_bytes: 16, max_source_bytes: 64, max_dimension: Some(32), ..limits() };
+
+        // 内容不是图像：如果先解码，错误会是无法解码
+        let garbage = format!("data:image/png;base64,{}", general_purpose::STANDARD.encode([0u8; 65]));
+        assert!(invalid_image_message(process_data_url(&garbage, &limits)).contains("MCP_MAX_SOURCE_IMAGE_BYTES"));
+
+        assert!(ensure_source_size("test", 64, &limits).is_ok());
+        assert!(ensure_source_size("test", 65, &limits).is_err());
+    }
+
+    #[test]
+    fn total_size_is_enforced_across_inputs() {
+        let image = png_data_url(8, 8);
+        let size = data_url_size(&image).unwrap();
+        let limits = InputImageConfig { max_total_bytes: size * 2, ..limits() };
+
+        let mut total_bytes = 0;
+        add_to_total_size(&mut total_bytes, &image, &limits).unwrap();
+        add_to_total_size(&mut total_bytes, "https://example.com/cat.png", &limits).unwrap();
+        add_to_total_size(&mut total_bytes, &image, &limits).unwrap();
+        let error = add_to_total_size(&mut total_bytes, &image, &limits).unwrap_err();
+        assert!(error.to_string().contains("MCP_MAX_TOTAL_IMAGE_BYTES"));
+    }
+
+    #[test]
+    fn sniffs_supported_formats() {
+        let cases = [
+            (image::ImageFormat::Png, "image/png"),
+            (image::ImageFormat::Jpeg, "image/jpeg"),
+            (image::ImageFormat::Gif, "image/gif"),
+            (image::ImageFormat::WebP, "image/webp"),
+            (image::ImageFormat::Bmp, "image/bmp"),
+            (image::ImageFormat::Tiff, "image/tiff"),
+        ];
+        for (format, mime_type) in cases {
+            assert_eq!(sniff_image_mime(&encode_sample(format)), Some(mime_type), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn truncated_headers_are_not_images() {
+        let png = encode_sample(image::ImageFormat::Png);
+        let webp = encode_sample(image::ImageFormat::WebP);
+        let bmp = encode_sample(image::ImageFormat::Bmp);
+
+        assert_eq!(sniff_image_mime(&[]), None);
+        assert_eq!(sniff_image_mime(&png[..7]), None);
+        assert_eq!(sniff_image_mime(&[0xFF, 0xD8]), None);
+        assert_eq!(sniff_image_mime(b"GIF8"), None);
+        assert_eq!(sniff_image_mime(&webp[..11]), None);
+        assert_eq!(sniff_image_mime(&bmp[..25]), None);
+        assert_eq!(sniff_image_mime(b"II*"), None);
+        // RIFF 容器中的其他格式不是 WebP
+        assert_eq!(sniff_image_mime(b"RIFF\0\0\0\0WAVEfmt "), None);
+    }
+
+    #[test]
+    fn text_file_renamed_to_png_is_rejected() {
+        let root = temp_dir("renamed-text");
+        fs::write(root.join("notes.png"), "这不是图像\n").unwrap();
+
+        let input = root.join("notes.png");
+        let error = detect_and_process_image_input(input.to_str().unwrap(), std::slice::from_ref(&root), &limits()).unwrap_err();
+        assert!(matches!(error.downcast::<ImageError>(), Ok(ImageError::InvalidImage(_))));
+    }
+
+    #[test]
+    fn mismatched_extension_uses_content_type() {
+        let root = temp_dir("renamed-jpeg");
+        fs::write(root.join("photo.png"), encode_sample(image::ImageFormat::Jpeg)).unwrap();
+
+        let input = root.join("photo.png");
+        let content = detect_and_process_image_input(input.to_str().unwrap(), std::slice::from_ref(&root), &limits()).unwrap();
+        assert!(content.data.starts_with("data:image/jpeg;base64,"));
+        assert!(content.warning.unwrap().contains("image/jpeg"));
+    }
+
+    #[test]
+    fn reads_file_inside_root() {
+        let root = temp_dir("inside");
+        write_png(&root.join("cat.png"));
+
+        let content = detect_and_process_image_input(root.join("cat.png").to_str().unwrap(), std::slice::from_ref(&root), &limits()).unwrap();
+        assert_eq!(content.content_type, "base64");
+        assert!(content.data.starts_with("data:image/png;base64,"));
+        assert!(content.warning.is_none());
+    }
+
+    #[test]
+    fn rejects_dot_dot_traversal() {
+        let base = temp_dir("traversal");
+        let root = base.join("root");
+        fs::create_dir_all(&root).unwrap();
+        write_png(&base.join("secret.png"));
+
+        let input = root.join("../secret.png");
+        assert!(is_path_not_allowed(detect_and_process_image_input(input.to_str().unwrap(), &[root], &limits())));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlink_escaping_root() {
+        let base = temp_dir("symlink");
+        let root = base.join("root");
+        fs::create_dir_all(&root).unwrap();
+        write_png(&base.join("secret.png"));
+        std::os::unix::fs::symlink(base.join("secret.png"), root.join("link.png")).unwrap();
+
+        let input = root.join("link.png");
+        assert!(is_path_not_allowed(detect_and_process_image_input(input.to_str().unwrap(), std::slice::from_ref(&root), &limits())));
+        assert!(read_saved_image(root.to_str().unwrap(), "link.png").is_err());
+    }
+
+    #[test]
+    fn sibling_prefix_is_not_inside_root() {
+        let base = temp_dir("sibling");
+        let (data, data2) = (base.join("data"), base.join("data2"));
+        fs::create_dir_all(&data).unwrap();
+        fs::create_dir_all(&data2).unwrap();
+        write_png(&data2.join("cat.png"));
+
+        let input = data2.join("cat.png");
+        assert!(is_path_not_allowed(detect_and_process_image_input(input.to_str().unwrap(), &[data], &limits())));
+    }
+
+    #[test]
+    fn missing_and_outside_files_get_the_same_error() {
+        let base = temp_dir("same-error");
+        let root = base.join("root");
+        fs::create_dir_all(&root).unwrap();
+        write_png(&base.join("outside.png"));
+
+        let message = |input: PathBuf| {
+            let input = input.to_str().unwrap().to_string();
+            let error = detect_and_process_image_input(&input, std::slice::from_ref(&root), &limits()).unwrap_err();
+            error.to_string().replace(&input, "<input>")
+        };
+        assert_eq!(message(base.join("outside.png")), message(base.join("missing.png")));
+        assert_eq!(message(base.join("outside.png")), message(root.join("missing.png")));
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_current_directory() {
+        // cargo test 的当前目录是 crate 根目录
+        let root = Path::new("target").join(format!("nano-banana-relative-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        write_png(&root.join("cat.png"));
+        let input = root.join("cat.png");
+
+        assert!(detect_and_process_image_input(input.to_str().unwrap(), std::slice::from_ref(&root), &limits()).is_ok());
+        let elsewhere = temp_dir("relative-elsewhere");
+        assert!(is_path_not_allowed(detect_and_process_image_input(input.to_str().unwrap(), &[elsewhere], &limits())));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn saved_response_images_use_the_data_url_type() {
+        let root = temp_dir("saved-types");
+        let save_dir = root.to_str().unwrap();
+        let jpeg = format!("data:image/jpeg;base64,{}", general_purpose::STANDARD.encode(encode_sample(image::ImageFormat::Jpeg)));
+
+        let saved = save_response_images(&[jpeg, png_data_url(2, 2)], Some(save_dir), Some("cat"), false);
+        let names: Vec<String> = saved.iter()
+            .map(|info| Path::new(info.saved_path.as_ref().unwrap()).file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["cat.jpg", "cat.png"]);
+        assert_eq!(read_saved_image(save_dir, "cat.jpg").unwrap().0, "image/jpeg");
+    }
+
+    #[test]
+    fn saved_image_type_comes_from_content() {
+        let root = temp_dir("saved-content");
+        fs::write(root.join("old.png"), encode_sample(image::ImageFormat::Jpeg)).unwrap();
+        fs::write(root.join("icon.svg"), "<svg xmlns=\"http://www.w3.org/2000/svg\"/>").unwrap();
+        fs::write(root.join("notes.txt"), "不是图像").unwrap();
+        let save_dir = root.to_str().unwrap();
+
+        assert_eq!(read_saved_image(save_dir, "old.png").unwrap().0, "image/jpeg");
+        assert_eq!(read_saved_image(save_dir, "icon.svg").unwrap().0, "image/svg+xml");
+        assert!(read_saved_image(save_dir, "notes.txt").is_err());
+
+        let listed: Vec<(String, String)> = list_saved_images(save_dir).unwrap().into_iter()
+            .map(|entry| (entry.filename, entry.mime_type))
+            .collect();
+        assert_eq!(listed, [
+            ("icon.svg".to_string(), "image/svg+xml".to_string()),
+            ("old.png".to_string(), "image/jpeg".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn read_saved_image_rejects_path_components() {
+        let root = temp_dir("saved");
+        write_png(&root.join("cat.png"));
+        let save_dir = root.to_str().unwrap();
+
+        assert_eq!(read_saved_image(save_dir, "cat.png").unwrap().0, "image/png");
+        for filename in ["", ".", "..", "../cat.png", "sub/cat.png", "missing.png"] {
+            assert!(read_saved_image(save_dir, filename).is_err(), "{}", filename);
+        }
+    }
+}
@@ -1,16 +1,36 @@
+mod backends;
 mod config;
+mod error;
 mod image_utils;
+mod rate_limiter;
+mod redact;
+mod resource_notifier;
+mod url_fetch;
 
 use anyhow::Result;
-use config::OpenRouterConfig;
+use backends::{
+	gemini::GeminiBackend, local_sd::LocalSdBackend, mock::MockBackend,
+	openai_images::OpenAiImagesBackend, openrouter::OpenRouterBackend, retry::{self, RequestAttempt},
+	BackendRegistry, ImageBackend, ImageRequest,
+};
+use error::ImageError;
+use config::{ChatUsage, CompareModelsParams, GetModelInfoParams, OpenRouterConfig};
+use rate_limiter::{ProgressReporter, RateLimiter};
+use resource_notifier::{ResourceNotifier, Subscriptions};
+use url_fetch::UrlFetcher;
 use rmcp::{
-	service::ServiceExt,
+	service::{NotificationContext, RequestContext, RoleServer, ServiceExt},
 	tool,
 	tool_handler,
 	tool_router,
 	handler::server::router::tool::ToolRouter,
 	handler::server::wrapper::Parameters,
-	model::{CallToolResult, Content, ServerCapabilities, ServerInfo},
+	model::{
+		AnnotateAble, CallToolResult, Content, ListResourceTemplatesResult, ListResourcesResult,
+		PaginatedRequestParam, RawResource, RawResourceTemplate, ReadResourceRequestParam,
+		ReadResourceResult, ResourceContents, ServerCapabilities, ServerInfo,
+		SubscribeRequestParam, UnsubscribeRequestParam,
+	},
 	transport::stdio,
 	ServerHandler,
 	ErrorData as McpError,
@@ -19,6 +39,44 @@ use rmcp::{
 use std::env;
 use serde::Deserialize;
 use serde_json::json;
+use base64::{Engine as _, engine::general_purpose};
+
+/// 保存目录中图像资源的 URI 前缀
+const IMAGE_RESOURCE_SCHEME: &str = "image://";
+
+/// 根据保存目录中的文件名生成资源 URI
+fn image_resource_uri(filename: &str) -> String {
+	format!("{}{}", IMAGE_RESOURCE_SCHEME, filename)
+}
+
+/// 收集已成功保存的图像对应的资源 URI
+fn saved_image_uris(saved_images: &[image_utils::ImageInfo]) -> Vec<String> {
+	saved_images
+		.iter()
+		.filter_map(|img_info| img_info.saved_path.as_deref())
+		.filter_map(|saved_path| std::path::Path::new(saved_path).file_name())
+		.filter_map(|name| name.to_str())
+		.map(image_resource_uri)
+		.collect()
+}
+
+/// 输入图像按与解析输入相同的顺序（原路径，其次保存目录）定位后，规范路径直接位于保存目录中时返回其文件名
+///
+/// URL、data URL 以及只是同名的其他文件不会被当作保存目录中的资源
+fn saved_input_filename(input: &str, save_dir: &str) -> Option<String> {
+	if input.starts_with("data:") || input.starts_with("http://") || input.starts_with("https://") {
+		return None;
+	}
+	let save_dir = std::path::Path::new(save_dir).canonicalize().ok()?;
+	let path = [std::path::PathBuf::from(input), save_dir.join(input)]
+		.into_iter()
+		.filter_map(|candidate| candidate.canonicalize().ok())
+		.find(|canonical| canonical.is_file())?;
+	if path.parent() != Some(save_dir.as_path()) {
+		return None;
+	}
+	path.file_name()?.to_str().map(str::to_string)
+}
 
 // 新增：导入SSE传输相关模块
 use rmcp::transport::sse_server::{SseServer, SseServerConfig};
@@ -27,10 +85,46 @@ use axum::serve;
 use tower_http::cors::{Any, CorsLayer};
 use axum::http::HeaderName;
 
+/// 工具结果中图像的返回方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageOutput {
+	/// 仅返回内联图像 (base64 + MIME 类型)
+	Image,
+	/// 仅返回保存后的文件路径
+	Path,
+	/// 同时返回内联图像和文件路径
+	#[default]
+	Both,
+}
+
+impl ImageOutput {
+	fn includes_image(self) -> bool {
+		matches!(self, ImageOutput::Image | ImageOutput::Both)
+	}
+
+	fn includes_path(self) -> bool {
+		matches!(self, ImageOutput::Path | ImageOutput::Both)
+	}
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct GenerateImageArgs {
 	#[schemars(example = &"一只可爱的小猫穿着宇航服在月球上行走，科幻风格")]
 	pub prompt: String,
+	/// 本次调用使用的模型，必须在支持列表中（默认使用服务器配置的模型）
+	#[schemars(example = &"google/gemini-2.5-flash-image-preview")]
+	pub model: Option<String>,
+	/// 最大生成 tokens 数（默认使用服务器配置）
+	pub max_tokens: Option<u32>,
+	/// 采样温度，范围 0.0 - 2.0（默认使用服务器配置）
+	pub temperature: Option<f32>,
+	/// 使用的后端名称（默认使用服务器配置的后端）
+	#[schemars(example = &"openrouter")]
+	pub backend: Option<String>,
+	/// 图像返回方式：image（内联图像）、path（文件路径）或 both（默认）
+	#[serde(default)]
+	pub output: ImageOutput,
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
@@ -41,6 +135,269 @@ pub struct EditImageArgs {
 	#[schemars(example = &"C:\\Images\\photo.png")]
 	#[schemars(example = &"data:image/jpeg;base64,/9j/4AAQ...")]
 	pub images: Vec<String>,
+	/// 本次调用使用的模型，必须在支持列表中（默认使用服务器配置的模型）
+	#[schemars(example = &"google/gemini-2.5-flash-image-preview")]
+	pub model: Option<String>,
+	/// 最大生成 tokens 数（默认使用服务器配置）
+	pub max_tokens: Option<u32>,
+	/// 采样温度，范围 0.0 - 2.0（默认使用服务器配置）
+	pub temperature: Option<f32>,
+	/// 使用的后端名称（默认使用服务器配置的后端）
+	#[schemars(example = &"openrouter")]
+	pub backend: Option<String>,
+	/// 图像返回方式：image（内联图像）、path（文件路径）或 both（默认）
+	#[serde(default)]
+	pub output: ImageOutput,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListModelsArgs {
+	/// 查询的后端名称（默认使用服务器配置的后端）
+	pub backend: Option<String>,
+}
+
+/// 本次调用实际使用的模型参数
+#[derive(Clone)]
+struct RequestOptions {
+	model: String,
+	max_tokens: u32,
+	temperature: f32,
+}
+
+impl RequestOptions {
+	/// 合并单次调用的覆盖参数与服务器配置，并按后端的允许列表校验模型
+	fn resolve(
+		config: &OpenRouterConfig,
+		backend: &dyn ImageBackend,
+		model: Option<String>,
+		max_tokens: Option<u32>,
+		temperature: Option<f32>,
+	) -> Result<Self, McpError> {
+		let model = model.unwrap_or_else(|| backend.default_model().to_string());
+		backend.validate_model(&model)?;
+
+		let max_tokens = config::check_max_tokens(max_tokens.unwrap_or(config.max_tokens))
+			.map_err(ImageError::InvalidParams)?;
+		let temperature = config::check_temperature(temperature.unwrap_or(config.temperature))
+			.map_err(ImageError::InvalidParams)?;
+
+		Ok(Self { model, max_tokens, temperature })
+	}
+
+	/// 构造发送给后端的请求
+	fn request(&self, prompt: String, images: Vec<String>) -> ImageRequest {
+		ImageRequest {
+			model: self.model.clone(),
+			prompt,
+			images,
+			max_tokens: self.max_tokens,
+			temperature: self.temperature,
+		}
+	}
+}
+
+/// 单个模型的一次调用结果，输出图像已保存
+struct ModelRun {
+	content: String,
+	saved_images: Vec<image_utils::ImageInfo>,
+	usage: Option<ChatUsage>,
+	attempts: Vec<RequestAttempt>,
+}
+
+/// 将 compare_models 的 message 拆分为提示词文本和输入图像
+fn split_message(message: serde_json::Value) -> Result<(String, Vec<String>), McpError> {
+	match message {
+		serde_json::Value::String(text) => Ok((text, Vec::new())),
+		serde_json::Value::Array(parts) => {
+			let mut texts = Vec::new();
+			let mut images = Vec::new();
+			for part in &parts {
+				match part.get("type").and_then(|t| t.as_str()) {
+					Some("text") => {
+						if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+							texts.push(text.to_string());
+						}
+					}
+					Some("image_url") => {
+						if let Some(url) = part.get("image_url").and_then(|u| u.get("url")).and_then(|u| u.as_str()) {
+							images.push(url.to_string());
+						}
+					}
+					_ => return Err(ImageError::InvalidParams(format!("不支持的内容类型: {}", part)).into()),
+				}
+			}
+			Ok((texts.join("\n"), images))
+		}
+		_ => Err(ImageError::InvalidParams("message 必须是字符串或内容数组".to_string()).into()),
+	}
+}
+
+/// 将模型 ID 转换为可用于文件名的片段，例如 google/gemini:free -> google_gemini_free
+fn model_slug(model: &str) -> String {
+	model
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+		.collect()
+}
+
+/// 按返回方式生成图像列表的文本摘要和内联图像内容
+fn image_result_contents(saved_images: &[image_utils::ImageInfo], output: ImageOutput) -> (String, Vec<Content>) {
+	let mut summary = String::new();
+	let mut images = Vec::new();
+
+	if saved_images.is_empty() {
+		return (summary, images);
+	}
+
+	summary.push_str(&format!("\n\n**生成的图像:** {} 张图像", saved_images.len()));
+	for (index, img_info) in saved_images.iter().enumerate() {
+		let inline = img_info.inline_data();
+		match inline {
+			Some((mime_type, _)) => summary.push_str(&format!("\n- 图像 {}: {}", index + 1, mime_type)),
+			None => summary.push_str(&format!("\n- 图像 {}: {}", index + 1, img_info.url)),
+		}
+		if output.includes_path() {
+			if let Some(saved_path) = &img_info.saved_path {
+				summary.push_str(&format!("\n  已保存到: {}", saved_path));
+			}
+		}
+		if output.includes_image() {
+			if let Some((mime_type, data)) = inline {
+				images.push(Content::image(data, mime_type));
+			}
+		}
+	}
+
+	(summary, images)
+}
+
+/// 一次带模型回退的生成结果
+struct GenerationRun {
+	/// 最终产生结果的模型
+	model: String,
+	run: ModelRun,
+	/// 被跳过的模型及原因
+	skipped: Vec<(String, String)>,
+}
+
+/// 保存模型输出图像的位置和文件名
+struct SaveTarget {
+	directory: String,
+	/// 基础文件名，None 时使用默认文件名
+	base_filename: Option<String>,
+	/// 编辑模式在文件名中添加 "edited" 标记
+	is_edit: bool,
+}
+
+/// 图像工具的操作类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageOperation {
+	/// 文本生成图像
+	Generate,
+	/// 根据指令编辑输入图像
+	Edit,
+}
+
+/// 图像工具共享管道的一次调用：generate_image、edit_image 等工具都转换为 ImageJob 后执行
+struct ImageJob {
+	operation: ImageOperation,
+	prompt: String,
+	/// 用户提供的原始图像输入：URL、data URL、本地路径或保存目录中的文件名
+	inputs: Vec<String>,
+	backend: Option<String>,
+	model: Option<String>,
+	max_tokens: Option<u32>,
+	temperature: Option<f32>,
+	output: ImageOutput,
+}
+
+impl ImageJob {
+	/// 保存输出图像时使用的基础文件名；编辑本地图片时保留原文件名
+	fn base_filename(&self) -> Option<String> {
+		match self.operation {
+			ImageOperation::Generate => Some("generated_image".to_string()),
+			ImageOperation::Edit => self.inputs.first()
+				.filter(|input| !input.starts_with("http://") && !input.starts_with("https://") && !input.starts_with("data:image/"))
+				.map(|input| image_utils::extract_filename_without_extension(input)),
+		}
+	}
+
+	/// 结果文本中描述本次调用输入的部分
+	fn summary(&self) -> String {
+		match self.operation {
+			ImageOperation::Generate => format!("**提示词:** {}", self.prompt),
+			ImageOperation::Edit => format!("**指令:** {}\n**输入图像:** {} 张图像", self.prompt, self.inputs.len()),
+		}
+	}
+}
+
+/// 解析后的输入图像，以及需要在结果中告知调用方的警告和处理说明
+#[derive(Default)]
+struct ResolvedInputs {
+	/// 发送给后端的图像：data URL 或通过检查的 http(s) URL
+	images: Vec<String>,
+	warnings: Vec<String>,
+	/// 缩放和重新编码的说明，包含原始尺寸
+	notes: Vec<String>,
+}
+
+impl ResolvedInputs {
+	fn notes_text(&self) -> String {
+		let mut text = String::new();
+		for warning in &self.warnings {
+			text.push_str(&format!("\n**输入警告:** {}", warning));
+		}
+		for note in &self.notes {
+			text.push_str(&format!("\n**输入处理:** {}", note));
+		}
+		text
+	}
+}
+
+/// 生成使用统计的文本摘要
+fn usage_text(usage: &ChatUsage) -> String {
+	format!("\n\n**使用统计:**\n- 提示词tokens: {}\n- 完成tokens: {}\n- 总tokens: {}",
+		usage.prompt_tokens, usage.completion_tokens, usage.total_tokens)
+}
+
+/// 限流等可重试错误和提供商返回的错误可以换一个模型再试
+fn should_fall_back(error: &McpError) -> bool {
+	let flag = |name: &str| error.data.as_ref()
+		.and_then(|data| data.get(name))
+		.and_then(|value| value.as_bool())
+		.unwrap_or(false);
+	flag("retryable") || flag("provider_error")
+}
+
+/// 生成回退过程的文本摘要
+fn fallback_text(skipped: &[(String, String)]) -> String {
+	if skipped.is_empty() {
+		return String::new();
+	}
+	let skipped: Vec<String> = skipped.iter()
+		.map(|(model, reason)| format!("{} ({})", model, reason))
+		.collect();
+	format!("\n**回退:** 已跳过 {}", skipped.join("，"))
+}
+
+/// 生成请求尝试记录的文本摘要，只有一次成功尝试时只显示次数
+fn attempts_text(attempts: &[RequestAttempt]) -> String {
+	let mut summary = format!("\n\n**请求尝试:** {} 次", attempts.len());
+	if attempts.len() <= 1 {
+		return summary;
+	}
+	for attempt in attempts {
+		summary.push_str(&format!("\n- 第 {} 次 ({:.2}s): ", attempt.attempt, attempt.elapsed_ms as f64 / 1000.0));
+		match (&attempt.error, attempt.status) {
+			(None, _) => summary.push_str("成功"),
+			(Some(_), Some(status)) => summary.push_str(&format!("失败，状态码 {}", status)),
+			(Some(error), None) => summary.push_str(&format!("失败，{}", error)),
+		}
+		if let Some(delay_ms) = attempt.retry_delay_ms {
+			summary.push_str(&format!("，等待 {:.1}s 后重试", delay_ms as f64 / 1000.0));
+		}
+	}
+	summary
 }
 
 
@@ -49,17 +406,43 @@ pub struct EditImageArgs {
 struct OpenRouterServer {
 	tool_router: ToolRouter<Self>,
 	config: OpenRouterConfig,
-	client: reqwest::Client,
+	backends: BackendRegistry,
+	rate_limiter: RateLimiter,
+	url_fetcher: UrlFetcher,
 	save_directory: std::sync::Arc<tokio::sync::RwLock<String>>,
+	notifier: ResourceNotifier,
+	// 当前会话订阅的资源，每个会话独立
+	subscriptions: Subscriptions,
 }
 
 #[tool_router]
 impl OpenRouterServer {
-	fn new(save_directory: Option<String>) -> Result<Self> {
+	async fn new(save_directory: Option<String>) -> Result<Self> {
 		let config = OpenRouterConfig::from_env()?;
-		let client = reqwest::Client::builder()
-			.default_headers(config.get_headers())
-			.build()?;
+
+		// 注册所有已配置的后端
+		let mut available_backends: Vec<std::sync::Arc<dyn ImageBackend>> = Vec::new();
+		if config.api_key.is_some() {
+			let mut openrouter = OpenRouterBackend::new(&config)?;
+			if config.validate_models_live {
+				openrouter.validate_supported_models().await?;
+			}
+			available_backends.push(std::sync::Arc::new(openrouter));
+		}
+		if let Some(gemini_config) = &config.gemini {
+			available_backends.push(std::sync::Arc::new(GeminiBackend::new(gemini_config, &config.http, config.log_payloads)?));
+		}
+		if let Some(openai_images_config) = &config.openai_images {
+			available_backends.push(std::sync::Arc::new(OpenAiImagesBackend::new(openai_images_config, &config.http, &config.url_fetch, config.log_payloads)?));
+		}
+		if let Some(local_sd_config) = &config.local_sd {
+			available_backends.push(std::sync::Arc::new(LocalSdBackend::new(local_sd_config, &config.http, config.log_payloads).await?));
+		}
+		// mock 后端只在显式选择时注册，避免生产环境的客户端误用
+		if config.backend == "mock" {
+			available_backends.push(std::sync::Arc::new(MockBackend::new()));
+		}
+		let backends = BackendRegistry::new(available_backends, &config.backend)?;
 		
 		// 确定保存目录：优先使用命令行参数，然后是环境变量，最后是默认值
 		let save_dir = if let Some(cmd_save_dir) = save_directory {
@@ -99,369 +482,598 @@ impl OpenRouterServer {
 			return Err(anyhow::anyhow!("保存目录路径 '{}' 不是一个有效的目录", save_dir));
 		}
 		
-		Ok(Self {
+		Ok(Self::with_backends(config, backends, save_dir))
+	}
+
+	/// 使用已注册的后端和已确认存在的保存目录创建服务器
+	fn with_backends(config: OpenRouterConfig, backends: BackendRegistry, save_dir: String) -> Self {
+		Self {
 			tool_router: Self::tool_router(),
+			rate_limiter: RateLimiter::new(&config.rate_limit),
+			url_fetcher: UrlFetcher::new(&config.url_fetch, &config.http),
 			config,
-			client,
+			backends,
 			save_directory: std::sync::Arc::new(tokio::sync::RwLock::new(save_dir)),
-		})
+			notifier: ResourceNotifier::default(),
+			subscriptions: Subscriptions::default(),
+		}
 	}
 
- 
-	#[tool(description = "文本生成图像")]
-	async fn generate_image(&self, Parameters(args): Parameters<GenerateImageArgs>) -> Result<CallToolResult, McpError> {
-		let url = format!("{}/chat/completions", self.config.base_url);
-		// 使用配置中的模型
-		let model = self.config.model.clone();
-		
-		// 构建消息内容
-		let content = vec![json!({
-			"type": "text",
-			"text": args.prompt
-		})];
-		
-		// generate_image 不需要处理图像输入，只需要文本提示词
-		
-		let request_body = json!({
-			"model": model,
-			"messages": [{
-				"role": "user",
-				"content": content
-			}],
-			"max_tokens": 1000,
-			"temperature": 0.7
-		});
-
-		match self.client.post(&url).json(&request_body).send().await {
-			Ok(response) => {
-				// 首先检查 HTTP 状态码
-				let status = response.status();
-				if !status.is_success() {
-					let error_text = response.text().await.unwrap_or_else(|_| "无法获取错误详情".to_string());
-					return Err(McpError::internal_error(
-						format!("API 请求失败，状态码: {}, 错误: {}", status, error_text), 
-						None
-					));
+	/// 依次尝试主模型和后端的回退模型，直到某个模型返回图像
+	///
+	/// 重试耗尽后仍可重试的错误（如 429）、提供商在 error 字段返回的错误以及没有返回图像都会切换到下一个模型，
+	/// 其他错误直接返回。最后一个模型没有返回图像时仍返回它的文本结果。
+	async fn generate_with_fallback(
+		&self,
+		backend: &dyn ImageBackend,
+		request: &ImageRequest,
+		operation: ImageOperation,
+		save: &SaveTarget,
+		progress: &ProgressReporter,
+	) -> Result<GenerationRun, McpError> {
+		let mut models = vec![request.model.clone()];
+		for model in backend.fallback_models() {
+			if !models.contains(model) {
+				models.push(model.clone());
+			}
+		}
+
+		let mut skipped: Vec<(String, String)> = Vec::new();
+		for (index, model) in models.iter().enumerate() {
+			let is_last = index + 1 == models.len();
+			let request = ImageRequest { model: model.clone(), ..request.clone() };
+			let outcome = self.run_single(backend, &request, operation, save, progress).await;
+
+			let reason = match outcome {
+				Ok(run) if is_last || !run.saved_images.is_empty() => {
+					return Ok(GenerationRun { model: model.clone(), run, skipped });
+				}
+				Ok(_) => "未返回图像".to_string(),
+				Err(e) if !is_last && should_fall_back(&e) => {
+					match e.data.as_ref().and_then(|data| data.get("status")).and_then(|s| s.as_u64()) {
+						Some(status) => format!("状态码 {}", status),
+						None => e.message.to_string(),
+					}
 				}
+				Err(mut e) => {
+					// 错误数据中记录已经跳过的模型，便于判断整个回退链的情况
+					if !skipped.is_empty() {
+						let skipped = json!(skipped.iter()
+							.map(|(model, reason)| json!({ "model": model, "reason": reason }))
+							.collect::<Vec<_>>());
+						error::insert_data(&mut e, "skipped_models", skipped);
+					}
+					return Err(e);
+				}
+			};
 
-				match response.json::<serde_json::Value>().await {
-					Ok(response_data) => {
-						// 添加调试信息，打印完整的响应
-						eprintln!("API 响应: {}", serde_json::to_string_pretty(&response_data).unwrap_or_else(|_| "无法序列化响应".to_string()));
-						
-						// 检查是否有错误字段
-						if let Some(error) = response_data.get("error") {
-							let error_message = error.get("message")
-								.and_then(|m| m.as_str())
-								.unwrap_or("未知错误");
-							return Err(McpError::internal_error(
-								format!("API 返回错误: {}", error_message), 
-								None
-							));
-						}
+			tracing::warn!(%model, %reason, "模型失败，尝试下一个回退模型");
+			skipped.push((model.clone(), reason));
+		}
 
-						// 检查 choices 字段
-						let choices = response_data.get("choices")
-							.and_then(|c| c.as_array())
-							.ok_or_else(|| McpError::internal_error(
-								"API 响应中缺少 'choices' 字段或格式不正确".to_string(), 
-								None
-							))?;
-
-						if choices.is_empty() {
-							return Err(McpError::internal_error(
-								"API 响应中 'choices' 数组为空".to_string(), 
-								None
-							));
-						}
+		unreachable!("回退链至少包含主模型")
+	}
+
+	/// 对单个模型执行一次调用：每次尝试都经过共享限流器并按配置重试，成功后保存返回的图像
+	///
+	/// generate_image/edit_image 的回退链和 compare_models 的每个模型都通过这里调用后端，失败时错误数据中包含模型。
+	async fn run_single(
+		&self,
+		backend: &dyn ImageBackend,
+		request: &ImageRequest,
+		operation: ImageOperation,
+		save: &SaveTarget,
+		progress: &ProgressReporter,
+	) -> Result<ModelRun, McpError> {
+		let (result, attempts) = retry::with_retries(&self.config.retry, || async {
+			// 每次尝试都重新排队，重试等待期间不占用并发槽位
+			let _permit = self.rate_limiter.acquire(progress).await;
+			match operation {
+				ImageOperation::Generate => backend.generate(request).await,
+				ImageOperation::Edit => backend.edit(request).await,
+			}
+		}).await.map_err(|e| error::with_model(e, &request.model))?;
 
-						let choice = &choices[0];
-						let message = choice.get("message")
-							.ok_or_else(|| McpError::internal_error("消息格式无效".to_string(), None))?;
-						
-						let content = message.get("content")
-							.and_then(|c| c.as_str())
-							.unwrap_or("无内容");
-						
-						let empty_vec: Vec<serde_json::Value> = Vec::new();
-						let images_array = message.get("images").and_then(|i| i.as_array()).unwrap_or(&empty_vec);
-						
-												// 使用当前设置的保存目录
-						let current_save_dir = {
-							let save_dir = self.save_directory.read().await;
-							save_dir.clone()
-						};
 		let saved_images = image_utils::save_response_images(
-			images_array, 
-			Some(&current_save_dir),
-			Some("generated_image"),
-			false // 不是编辑模式
+			&result.images,
+			Some(&save.directory),
+			save.base_filename.as_deref(),
+			save.is_edit
 		);
-		
-		let mut response_text = format!("**模型:** {}\n**提示词:** {}\n**响应:** {}", 
-			model, args.prompt, content);
-						
-						if !images_array.is_empty() {
-							response_text.push_str(&format!("\n\n**生成的图像:** {} 张图像", images_array.len()));
-							for (index, img_info) in saved_images.iter().enumerate() {
-								response_text.push_str(&format!("\n- 图像 {}: {}...", index + 1, 
-									&img_info.url[..std::cmp::min(50, img_info.url.len())]));
-								if let Some(saved_path) = &img_info.saved_path {
-									response_text.push_str(&format!("\n  已保存到: {}", saved_path));
-								}
-							}
-						}
+		Ok(ModelRun {
+			content: result.text,
+			saved_images,
+			usage: result.usage,
+			attempts,
+		})
+	}
 
-						// 添加使用统计
-						if let Some(usage) = response_data.get("usage") {
-							if let (Some(prompt_tokens), Some(completion_tokens), Some(total_tokens)) = (
-								usage.get("prompt_tokens").and_then(|t| t.as_u64()),
-								usage.get("completion_tokens").and_then(|t| t.as_u64()),
-								usage.get("total_tokens").and_then(|t| t.as_u64())
-							) {
-								response_text.push_str(&format!("\n\n**使用统计:**\n- 提示词tokens: {}\n- 完成tokens: {}\n- 总tokens: {}", 
-									prompt_tokens, completion_tokens, total_tokens));
-							}
-						}
+	/// 允许读取本地输入图像的根目录：未配置时只允许当前保存目录
+	fn input_roots(&self, save_dir: &str) -> Vec<std::path::PathBuf> {
+		if self.config.input_roots.is_empty() {
+			vec![std::path::PathBuf::from(save_dir)]
+		} else {
+			self.config.input_roots.clone()
+		}
+	}
 
-						Ok(CallToolResult::success(vec![Content::text(response_text)]))
-					}
-					Err(e) => Err(McpError::internal_error(format!("解析响应失败: {}", e), None)),
+	/// 解析图像输入：先直接处理，失败后在保存目录中查找
+	///
+	/// URL 输入经过域名检查，启用下载时替换为 data URL。无法解析的输入、允许目录之外的本地文件
+	/// 以及内容不是图像的文件都返回错误，不会把原始字符串转发给后端。内联图像按单张和总大小上限检查，
+	/// 启用缩放时在发送前缩小并重新编码。
+	async fn resolve_image_inputs(&self, inputs: &[String], save_dir: &str) -> Result<ResolvedInputs, McpError> {
+		let input_roots = self.input_roots(save_dir);
+		let limits = &self.config.input_images;
+		let mut resolved = ResolvedInputs::default();
+		let mut total_bytes = 0;
+		for image_input in inputs {
+			// 首先尝试直接处理图像输入，本地路径不可用时在 save_directory 中查找
+			let result = match image_utils::detect_and_process_image_input(image_input, &input_roots, limits) {
+				Err(e) if matches!(e.downcast_ref::<ImageError>(), Some(ImageError::PathNotAllowed { .. })) => {
+					image_utils::find_image_in_save_directory(image_input, save_dir, &input_roots, limits)
 				}
+				result => result,
+			};
+			let mut image_content = result.map_err(|e| McpError::from(e.downcast::<ImageError>().unwrap_or_else(|e| {
+				ImageError::InvalidImage(format!("无法读取图像输入 '{}': {}", image_input, e))
+			})))?;
+
+			if let Some(warning) = image_content.warning.take() {
+				tracing::warn!("{}", warning);
+				resolved.warnings.push(warning);
 			}
-			Err(e) => Err(McpError::internal_error(format!("请求失败: {}", e), None)),
+			// 下载得到的 data URL 与直接传入的 data URL 一样检查大小并按需缩放
+			if image_content.content_type == "url" {
+				let fetched = self.url_fetcher.resolve(&image_content.data).await?;
+				if fetched.starts_with("data:") {
+					image_content = image_utils::process_data_url(&fetched, limits)
+						.map_err(|e| McpError::from(e.downcast::<ImageError>().unwrap_or_else(|e| ImageError::InvalidImage(e.to_string()))))?;
+				} else {
+					image_content.data = fetched;
+				}
+			}
+			if let Some(note) = image_content.note {
+				tracing::info!("{}", note);
+				resolved.notes.push(note);
+			}
+
+			image_utils::add_to_total_size(&mut total_bytes, &image_content.data, limits)?;
+			resolved.images.push(image_content.data);
+		}
+		Ok(resolved)
+	}
+
+	/// 图像工具共享的请求/响应管道：解析参数和输入图像、带回退地调用后端、保存结果、发送资源更新通知并生成工具结果
+	///
+	/// 解析、保存和结果格式的修改都在这里完成，所有图像工具同时生效。
+	async fn run_image_job(&self, job: ImageJob, context: &RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+		let backend = self.backends.get(job.backend.as_deref())?;
+		let options = RequestOptions::resolve(&self.config, backend.as_ref(), job.model.clone(), job.max_tokens, job.temperature)?;
+
+		// 使用当前设置的保存目录
+		let current_save_dir = {
+			let save_dir = self.save_directory.read().await;
+			save_dir.clone()
+		};
+
+		let inputs = self.resolve_image_inputs(&job.inputs, &current_save_dir).await?;
+
+		// 编辑模式使用递增文件名，保留原文件名并添加 "edited" 标记
+		let save = SaveTarget {
+			directory: current_save_dir.clone(),
+			base_filename: job.base_filename(),
+			is_edit: job.operation == ImageOperation::Edit,
+		};
+		let progress = ProgressReporter::new(context);
+		let GenerationRun { model, run, skipped } =
+			self.generate_with_fallback(backend.as_ref(), &options.request(job.prompt.clone(), inputs.images.clone()), job.operation, &save, &progress).await?;
+
+		self.notify_images_changed(&run.saved_images, &job.inputs, &current_save_dir).await;
+
+		let mut response_text = format!("**模型:** {}{}\n{}", model, fallback_text(&skipped), job.summary());
+		response_text.push_str(&inputs.notes_text());
+		response_text.push_str(&format!("\n**响应:** {}", run.content));
+
+		let (images_text, image_contents) = image_result_contents(&run.saved_images, job.output);
+		response_text.push_str(&images_text);
+
+		// 添加使用统计
+		if let Some(usage) = &run.usage {
+			response_text.push_str(&usage_text(usage));
+		}
+		response_text.push_str(&attempts_text(&run.attempts));
+
+		let mut contents = vec![Content::text(response_text)];
+		contents.extend(image_contents);
+		Ok(CallToolResult::success(contents))
+	}
+
+	/// 新保存的图像以及被编辑的源图像都视为已更新的资源，没有保存任何图像时不发送通知
+	async fn notify_images_changed(&self, saved_images: &[image_utils::ImageInfo], inputs: &[String], save_dir: &str) {
+		let mut changed_uris = saved_image_uris(saved_images);
+		if changed_uris.is_empty() {
+			return;
 		}
+		changed_uris.extend(inputs.iter().filter_map(|input| saved_input_filename(input, save_dir)).map(|name| image_resource_uri(&name)));
+		self.notifier.notify_images_changed(&changed_uris).await;
+	}
+
+	/// 为新会话创建处理器：共享配置和通知注册表，但使用独立的订阅集合
+	fn for_session(&self) -> Self {
+		Self {
+			subscriptions: Subscriptions::default(),
+			..self.clone()
+		}
+	}
+
+ 
+	#[tool(description = "文本生成图像")]
+	async fn generate_image(&self, Parameters(args): Parameters<GenerateImageArgs>, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+		self.run_image_job(ImageJob {
+			operation: ImageOperation::Generate,
+			prompt: args.prompt,
+			inputs: Vec::new(),
+			backend: args.backend,
+			model: args.model,
+			max_tokens: args.max_tokens,
+			temperature: args.temperature,
+			output: args.output,
+		}, &context).await
 	}
 
 	#[tool(description = "使用图像模型编辑或分析图像（支持多张图像）。图像可以是：1) URL链接 2) base64编码数据 3) 本地文件路径")]
-	async fn edit_image(&self, Parameters(args): Parameters<EditImageArgs>) -> Result<CallToolResult, McpError> {
+	async fn edit_image(&self, Parameters(args): Parameters<EditImageArgs>, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
 		// 验证是否传入了图片
 		if args.images.is_empty() {
-			return Err(McpError::internal_error(
-				"❌ 编辑图像时必须传入至少一张图片！\n\n请提供以下格式之一的图片：\n- URL链接 (http:// 或 https://)\n- base64编码数据 (data:image/...)\n- 本地文件路径\n\n示例：\n- URL: https://example.com/image.jpg\n- 本地文件: C:\\Images\\photo.png\n- base64: data:image/jpeg;base64,/9j/4AAQ...", 
-				None
-			));
+			return Err(ImageError::InvalidParams(
+				"❌ 编辑图像时必须传入至少一张图片！\n\n请提供以下格式之一的图片：\n- URL链接 (http:// 或 https://)\n- base64编码数据 (data:image/...)\n- 本地文件路径\n\n示例：\n- URL: https://example.com/image.jpg\n- 本地文件: C:\\Images\\photo.png\n- base64: data:image/jpeg;base64,/9j/4AAQ...".to_string(),
+			).into());
 		}
 
-		let url = format!("{}/chat/completions", self.config.base_url);
-		// 使用配置中的模型
-		let model = self.config.model.clone();
-		
-		// 构建包含文本指令和图像的内容数组
-		let mut content = vec![json!({
-			"type": "text",
-			"text": args.instruction
-		})];
-		
-		// 处理每个图像输入，支持多种格式
-		for image_input in &args.images {
-			// 首先尝试直接处理图像输入
-			match image_utils::detect_and_process_image_input(image_input) {
-				Ok(image_content) => {
-					match image_content.content_type.as_str() {
-						"url" => {
-							// URL 格式，直接使用
-							content.push(json!({
-								"type": "image_url",
-								"image_url": {
-									"url": image_content.data
-								}
-							}));
-						}
-						"base64" => {
-							// base64 格式，直接使用
-							content.push(json!({
-								"type": "image_url",
-								"image_url": {
-									"url": image_content.data
-								}
-							}));
-						}
-						_ => {
-							// 其他格式，作为 base64 处理
-							content.push(json!({
-								"type": "image_url",
-								"image_url": {
-									"url": image_content.data
-								}
-							}));
+		self.run_image_job(ImageJob {
+			operation: ImageOperation::Edit,
+			prompt: args.instruction,
+			inputs: args.images,
+			backend: args.backend,
+			model: args.model,
+			max_tokens: args.max_tokens,
+			temperature: args.temperature,
+			output: args.output,
+		}, &context).await
+	}
+
+	#[tool(description = "将同一提示词同时发送给多个图像模型，保存各自的输出（文件名包含模型标识），并返回包含耗时、token 使用和文件路径的对比报告")]
+	async fn compare_models(&self, Parameters(params): Parameters<CompareModelsParams>, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+		// 去重并保持顺序
+		let mut models: Vec<String> = Vec::new();
+		for model in params.models {
+			if !models.contains(&model) {
+				models.push(model);
+			}
+		}
+		if models.is_empty() {
+			return Err(ImageError::InvalidParams("compare_models 至少需要一个模型".to_string()).into());
+		}
+
+		let (prompt, inputs) = split_message(params.message)?;
+
+		let backend = self.backends.get(params.backend.as_deref())?;
+		let mut options = Vec::with_capacity(models.len());
+		for model in &models {
+			options.push(RequestOptions::resolve(&self.config, backend.as_ref(), Some(model.clone()), params.max_tokens, None)?);
+		}
+
+		let current_save_dir = {
+			let save_dir = self.save_directory.read().await;
+			save_dir.clone()
+		};
+
+		// 输入图像与 edit_image 使用相同的解析和安全检查
+		let resolved = self.resolve_image_inputs(&inputs, &current_save_dir).await?;
+
+		// 带输入图像时按编辑处理，否则不支持图像输入的后端会把对比变成纯文生图
+		let (operation, is_edit) = if resolved.images.is_empty() {
+			(ImageOperation::Generate, false)
+		} else {
+			(ImageOperation::Edit, true)
+		};
+
+		// 并发请求所有模型，实际并发数受共享限流器约束
+		let progress = std::sync::Arc::new(ProgressReporter::new(&context));
+		let mut tasks = tokio::task::JoinSet::new();
+		for (index, options) in options.into_iter().enumerate() {
+			let server = self.clone();
+			let backend = backend.clone();
+			let progress = progress.clone();
+			let request = options.request(prompt.clone(), resolved.images.clone());
+			// 文件名包含模型标识，便于区分不同模型的输出
+			let save = SaveTarget {
+				directory: current_save_dir.clone(),
+				base_filename: Some(format!("compare_{}", model_slug(&request.model))),
+				is_edit,
+			};
+			tasks.spawn(async move {
+				let started = std::time::Instant::now();
+				let result = server.run_single(backend.as_ref(), &request, operation, &save, &progress).await;
+				(index, started.elapsed(), result)
+			});
+		}
+
+		let mut runs: Vec<Option<(std::time::Duration, Result<ModelRun, McpError>)>> = (0..models.len()).map(|_| None).collect();
+		while let Some(joined) = tasks.join_next().await {
+			let (index, elapsed, result) = joined
+				.map_err(|e| McpError::from(ImageError::Internal(format!("对比任务执行失败: {}", e))))?;
+			runs[index] = Some((elapsed, result));
+		}
+
+		let mut response_text = format!("**模型对比:** {} 个模型", models.len());
+		response_text.push_str(&resolved.notes_text());
+		let mut saved_images = Vec::new();
+		for (model, run) in models.iter().zip(runs.into_iter().flatten()) {
+			let (elapsed, result) = run;
+			response_text.push_str(&format!("\n\n### {}\n- 耗时: {:.2}s", model, elapsed.as_secs_f64()));
+			match result {
+				Ok(run) => {
+					response_text.push_str(&format!("\n- 响应: {}", run.content));
+					if let Some(usage) = &run.usage {
+						response_text.push_str(&format!("\n- tokens: 提示词 {} / 完成 {} / 总计 {}",
+							usage.prompt_tokens, usage.completion_tokens, usage.total_tokens));
+					}
+					if run.attempts.len() > 1 {
+						response_text.push_str(&format!("\n- 请求尝试: {} 次", run.attempts.len()));
+					}
+					if run.saved_images.is_empty() {
+						response_text.push_str("\n- 图像: 无");
+					}
+					for img_info in &run.saved_images {
+						if let Some(saved_path) = &img_info.saved_path {
+							response_text.push_str(&format!("\n- 图像: {}", saved_path));
 						}
 					}
+					saved_images.extend(run.saved_images);
 				}
-				Err(_) => {
-					// 如果直接处理失败，尝试在 save_directory 中查找
-					let current_save_dir = {
-						let save_dir = self.save_directory.read().await;
-						save_dir.clone()
-					};
-					
-					match image_utils::find_image_in_save_directory(image_input, &current_save_dir) {
-						Ok(image_content) => {
-							// 在 save_directory 中找到图片，转换为 base64 格式
-							content.push(json!({
-								"type": "image_url",
-								"image_url": {
-									"url": image_content.data
-								}
-							}));
-						}
-						Err(e) => {
-							// 在 save_directory 中也找不到，记录错误但继续处理其他图像
-							eprintln!("处理图像输入 '{}' 失败: {}", image_input, e);
-							// 尝试作为 URL 处理（保持向后兼容性）
-							content.push(json!({
-								"type": "image_url",
-								"image_url": {
-									"url": image_input
-								}
-							}));
-						}
+				Err(e) => {
+					response_text.push_str(&format!("\n- ❌ 失败: {}", e.message));
+					let attempt_count = e.data.as_ref()
+						.and_then(|data| data.get("attempts"))
+						.and_then(|attempts| attempts.as_array())
+						.map_or(0, Vec::len);
+					if attempt_count > 1 {
+						response_text.push_str(&format!("\n- 请求尝试: {} 次", attempt_count));
 					}
 				}
 			}
 		}
 
-		let request_body = json!({
-			"model": model,
-			"messages": [{
-				"role": "user",
-				"content": content
-			}],
-			"max_tokens": 1000,
-			"temperature": 0.7
-		});
-
-		match self.client.post(&url).json(&request_body).send().await {
-			Ok(response) => {
-				// 首先检查 HTTP 状态码
-				let status = response.status();
-				if !status.is_success() {
-					let error_text = response.text().await.unwrap_or_else(|_| "无法获取错误详情".to_string());
-					return Err(McpError::internal_error(
-						format!("API 请求失败，状态码: {}, 错误: {}", status, error_text), 
-						None
-					));
-				}
-
-				match response.json::<serde_json::Value>().await {
-					Ok(response_data) => {
-						// 添加调试信息，打印完整的响应
-						eprintln!("API 响应: {}", serde_json::to_string_pretty(&response_data).unwrap_or_else(|_| "无法序列化响应".to_string()));
-						
-						// 检查是否有错误字段
-						if let Some(error) = response_data.get("error") {
-							let error_message = error.get("message")
-								.and_then(|m| m.as_str())
-								.unwrap_or("未知错误");
-							return Err(McpError::internal_error(
-								format!("API 返回错误: {}", error_message), 
-								None
-							));
-						}
+		// compare_models 不修改输入图像，只通知新保存的图像
+		self.notify_images_changed(&saved_images, &[], &current_save_dir).await;
 
-						// 检查 choices 字段
-						let choices = response_data.get("choices")
-							.and_then(|c| c.as_array())
-							.ok_or_else(|| McpError::internal_error(
-								"API 响应中缺少 'choices' 字段或格式不正确".to_string(), 
-								None
-							))?;
-
-						if choices.is_empty() {
-							return Err(McpError::internal_error(
-								"API 响应中 'choices' 数组为空".to_string(), 
-								None
-							));
-						}
+		Ok(CallToolResult::success(vec![Content::text(response_text)]))
+	}
 
-						let choice = &choices[0];
-						let message = choice.get("message")
-							.ok_or_else(|| McpError::internal_error("消息格式无效".to_string(), None))?;
-						
-						let content = message.get("content")
-							.and_then(|c| c.as_str())
-							.unwrap_or("无内容");
-						
-						let empty_vec: Vec<serde_json::Value> = Vec::new();
-						let images_array = message.get("images").and_then(|i| i.as_array()).unwrap_or(&empty_vec);
-						
-						// 使用当前设置的保存目录
-						let current_save_dir = {
-							let save_dir = self.save_directory.read().await;
-							save_dir.clone()
-						};
-						// 为编辑图像提取基础文件名（如果是本地图片）
-						let base_filename = if !args.images.is_empty() {
-							// 尝试从第一个本地图片路径提取文件名
-							let first_image = &args.images[0];
-							if !first_image.starts_with("http://") && !first_image.starts_with("https://") && !first_image.starts_with("data:image/") {
-								// 这是一个本地文件路径，提取文件名
-								Some(image_utils::extract_filename_without_extension(first_image))
-							} else {
-								None
-							}
-						} else {
-							None
-						};
-						
-						// 为编辑图像使用递增文件名，保留原文件名并添加 "edited" 标记
-						let saved_images = image_utils::save_response_images(
-							images_array, 
-							Some(&current_save_dir),
-							base_filename.as_deref(),
-							true // 是编辑模式
-						);
-						
-						let mut response_text = format!("**模型:** {}\n**指令:** {}\n**输入图像:** {} 张图像\n**响应:** {}", 
-							model, args.instruction, args.images.len(), content);
-						
-						if !images_array.is_empty() {
-							response_text.push_str(&format!("\n\n**生成的图像:** {} 张图像", images_array.len()));
-							for (index, img_info) in saved_images.iter().enumerate() {
-								response_text.push_str(&format!("\n- 图像 {}: {}...", index + 1, 
-									&img_info.url[..std::cmp::min(50, img_info.url.len())]));
-								if let Some(saved_path) = &img_info.saved_path {
-									response_text.push_str(&format!("\n  已保存到: {}", saved_path));
-								}
-							}
-						}
+	#[tool(description = "列出后端上可以输出图像的模型，包括 ID、名称、上下文长度和价格")]
+	async fn list_models(&self, Parameters(args): Parameters<ListModelsArgs>, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+		let backend = self.backends.get(args.backend.as_deref())?;
+		let models = {
+			let _permit = self.rate_limiter.acquire(&ProgressReporter::new(&context)).await;
+			backend.list_models().await?
+		};
 
-						// 添加使用统计
-						if let Some(usage) = response_data.get("usage") {
-							if let (Some(prompt_tokens), Some(completion_tokens), Some(total_tokens)) = (
-								usage.get("prompt_tokens").and_then(|t| t.as_u64()),
-								usage.get("completion_tokens").and_then(|t| t.as_u64()),
-								usage.get("total_tokens").and_then(|t| t.as_u64())
-							) {
-								response_text.push_str(&format!("\n\n**使用统计:**\n- 提示词tokens: {}\n- 完成tokens: {}\n- 总tokens: {}", 
-									prompt_tokens, completion_tokens, total_tokens));
-							}
-						}
+		let image_models: Vec<_> = models
+			.iter()
+			.filter(|model| model.supports_image_output())
+			.collect();
 
-						Ok(CallToolResult::success(vec![Content::text(response_text)]))
-					}
-					Err(e) => Err(McpError::internal_error(format!("解析响应失败: {}", e), None)),
+		let mut response_text = format!("**可输出图像的模型:** {} 个", image_models.len());
+		for model in image_models {
+			response_text.push_str(&format!("\n\n- **{}** ({})", model.id, model.name));
+			if backend.supported_models().contains(&model.id) {
+				response_text.push_str(" ✅ 已允许");
+			}
+			if let Some(context_length) = model.context_length {
+				response_text.push_str(&format!("\n  上下文长度: {}", context_length));
+			}
+			if let Some(pricing) = &model.pricing {
+				response_text.push_str(&format!("\n  价格 (USD/token): 提示词 {}, 完成 {}", pricing.prompt, pricing.completion));
+				if let Some(image) = &pricing.image {
+					response_text.push_str(&format!(", 图像 {}", image));
 				}
 			}
-			Err(e) => Err(McpError::internal_error(format!("请求失败: {}", e), None)),
 		}
+
+		Ok(CallToolResult::success(vec![Content::text(response_text)]))
 	}
 
+	#[tool(description = "获取单个模型的完整信息：价格、审核标记、最大完成 tokens、支持的模态，以及是否在服务器允许的模型列表中")]
+	async fn get_model_info(&self, Parameters(params): Parameters<GetModelInfoParams>, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+		let backend = self.backends.get(params.backend.as_deref())?;
+		let models = {
+			let _permit = self.rate_limiter.acquire(&ProgressReporter::new(&context)).await;
+			backend.list_models().await?
+		};
+
+		let model = models
+			.iter()
+			.find(|model| model.id == params.model)
+			.ok_or_else(|| McpError::from(ImageError::ModelNotFound {
+				backend: backend.name().to_string(),
+				model: params.model.clone(),
+			}))?;
+
+		let mut response_text = format!("**模型:** {}\n**名称:** {}", model.id, model.name);
+		if let Some(description) = &model.description {
+			response_text.push_str(&format!("\n**描述:** {}", description));
+		}
+		if let Some(context_length) = model.context_length {
+			response_text.push_str(&format!("\n**上下文长度:** {}", context_length));
+		}
+
+		if let Some(pricing) = &model.pricing {
+			response_text.push_str(&format!("\n\n**价格 (USD/token):**\n- 提示词: {}\n- 完成: {}", pricing.prompt, pricing.completion));
+			if let Some(image) = &pricing.image {
+				response_text.push_str(&format!("\n- 图像: {}", image));
+			}
+		}
+
+		if let Some(provider) = &model.top_provider {
+			response_text.push_str("\n\n**提供方:**");
+			match provider.max_completion_tokens {
+				Some(max_completion_tokens) => response_text.push_str(&format!("\n- 最大完成tokens: {}", max_completion_tokens)),
+				None => response_text.push_str("\n- 最大完成tokens: 未知"),
+			}
+			match provider.is_moderated {
+				Some(is_moderated) => response_text.push_str(&format!("\n- 内容审核: {}", if is_moderated { "是" } else { "否" })),
+				None => response_text.push_str("\n- 内容审核: 未知"),
+			}
+		}
+
+		if let Some(architecture) = &model.architecture {
+			response_text.push_str("\n\n**模态:**");
+			if let Some(input_modalities) = &architecture.input_modalities {
+				response_text.push_str(&format!("\n- 输入: {}", input_modalities.join(", ")));
+			}
+			if let Some(output_modalities) = &architecture.output_modalities {
+				response_text.push_str(&format!("\n- 输出: {}", output_modalities.join(", ")));
+			}
+			if let Some(modality) = &architecture.modality {
+				response_text.push_str(&format!("\n- modality: {}", modality));
+			}
+		}
+
+		// 说明该模型能否在本服务器上使用，便于解释模型选择被拒绝的原因
+		response_text.push_str("\n\n**服务器允许:** ");
+		if backend.supported_models().contains(&model.id) {
+			response_text.push('是');
+		} else {
+			response_text.push_str(&format!("否（支持的模型: {}）", backend.supported_models().join(", ")));
+			if !model.supports_image_output() {
+				response_text.push_str("\n该模型不支持图像输出");
+			}
+		}
 
+		Ok(CallToolResult::success(vec![Content::text(response_text)]))
+	}
 }
 
 #[tool_handler]
 impl ServerHandler for OpenRouterServer {
 	fn get_info(&self) -> ServerInfo {
 		ServerInfo {
-			instructions: Some("nano banana MCP - 提供 OpenRouter API 访问 google/gemini-2.5-flash-image模型。支持多种图像输入格式：URL、base64、本地文件路径。可用工具: generate_image, edit_image。模型和保存目录只能通过命令行参数或环境变量设置。".into()),
+			instructions: Some("nano banana MCP - 提供 OpenRouter API 访问 google/gemini-2.5-flash-image模型。支持多种图像输入格式：URL、base64、本地文件路径。可用工具: generate_image, edit_image, compare_models, list_models, get_model_info。保存目录中的图像以 image://<文件名> 资源形式提供。默认模型和保存目录通过命令行参数或环境变量设置，图像工具可通过 model、max_tokens、temperature 参数按次覆盖（模型须在支持列表中）。".into()),
 			capabilities: ServerCapabilities::builder()
 				.enable_tools()
 				.enable_resources()
+				.enable_resources_list_changed()
+				.enable_resources_subscribe()
 				.build(),
 			..Default::default()
 		}
 	}
+
+	async fn list_resources(
+		&self,
+		_request: Option<PaginatedRequestParam>,
+		_context: RequestContext<RoleServer>,
+	) -> Result<ListResourcesResult, McpError> {
+		let current_save_dir = {
+			let save_dir = self.save_directory.read().await;
+			save_dir.clone()
+		};
+
+		let entries = image_utils::list_saved_images(&current_save_dir).map_err(|e| {
+			McpError::internal_error(format!("读取保存目录 '{}' 失败: {}", current_save_dir, e), None)
+		})?;
+
+		let resources = entries
+			.into_iter()
+			.map(|entry| {
+				let resource = RawResource {
+					uri: image_resource_uri(&entry.filename),
+					name: entry.filename,
+					title: None,
+					description: None,
+					mime_type: Some(entry.mime_type),
+					size: u32::try_from(entry.size).ok(),
+					icons: None,
+				};
+				match entry.created {
+					Some(created) => resource.with_timestamp(created),
+					None => resource.no_annotation(),
+				}
+			})
+			.collect();
+
+		Ok(ListResourcesResult::with_all_items(resources))
+	}
+
+	async fn list_resource_templates(
+		&self,
+		_request: Option<PaginatedRequestParam>,
+		_context: RequestContext<RoleServer>,
+	) -> Result<ListResourceTemplatesResult, McpError> {
+		let template = RawResourceTemplate {
+			uri_template: format!("{}{{filename}}", IMAGE_RESOURCE_SCHEME),
+			name: "saved-image".to_string(),
+			title: Some("保存目录中的图像".to_string()),
+			description: Some("按文件名读取保存目录中的图像，例如 image://generated_image.png".to_string()),
+			mime_type: None,
+		};
+		Ok(ListResourceTemplatesResult::with_all_items(vec![template.no_annotation()]))
+	}
+
+	async fn subscribe(
+		&self,
+		request: SubscribeRequestParam,
+		_context: RequestContext<RoleServer>,
+	) -> Result<(), McpError> {
+		if !request.uri.starts_with(IMAGE_RESOURCE_SCHEME) {
+			return Err(McpError::resource_not_found(format!("不支持的资源 URI: {}", request.uri), None));
+		}
+		self.subscriptions.lock().unwrap().insert(request.uri);
+		Ok(())
+	}
+
+	async fn unsubscribe(
+		&self,
+		request: UnsubscribeRequestParam,
+		_context: RequestContext<RoleServer>,
+	) -> Result<(), McpError> {
+		self.subscriptions.lock().unwrap().remove(&request.uri);
+		Ok(())
+	}
+
+	async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+		tracing::info!("client initialized");
+		self.notifier.register(context.peer, self.subscriptions.clone());
+	}
+
+	async fn read_resource(
+		&self,
+		request: ReadResourceRequestParam,
+		_context: RequestContext<RoleServer>,
+	) -> Result<ReadResourceResult, McpError> {
+		let filename = request.uri.strip_prefix(IMAGE_RESOURCE_SCHEME).ok_or_else(|| {
+			McpError::resource_not_found(format!("不支持的资源 URI: {}", request.uri), None)
+		})?;
+
+		let current_save_dir = {
+			let save_dir = self.save_directory.read().await;
+			save_dir.clone()
+		};
+
+		let (mime_type, bytes) = image_utils::read_saved_image(&current_save_dir, filename)
+			.map_err(|e| McpError::resource_not_found(e.to_string(), Some(json!({ "uri": request.uri }))))?;
+
+		Ok(ReadResourceResult {
+			contents: vec![ResourceContents::BlobResourceContents {
+				uri: request.uri,
+				mime_type: Some(mime_type),
+				blob: general_purpose::STANDARD.encode(&bytes),
+				meta: None,
+			}],
+		})
+	}
 }
 
 fn print_usage() {
@@ -486,16 +1098,82 @@ fn print_usage() {
 	println!("  --model=MODEL                             # 设置使用的模型");
 	println!("  --save-directory=PATH                     # 设置图片保存目录 (必须是绝对路径)");
 	println!("  -s PATH                                   # --save-directory 的简写形式");
+	println!("  --input-roots=DIR1:DIR2                   # 允许读取本地输入图像的目录 (默认: 保存目录；Windows 使用 ; 分隔)");
+	println!("  --fetch-urls                              # 由服务器下载 URL 图像输入 (拒绝内网地址，限制大小并校验内容)");
+	println!("  --url-allowed-domains=D1,D2               # 允许的 URL 图像域名 (包含子域名)，逗号分隔");
+	println!("  --url-denied-domains=D1,D2                # 拒绝的 URL 图像域名 (包含子域名)，逗号分隔");
+	println!("  --max-image-bytes=N                       # 单张输入图像的最大字节数 (默认: 20971520)");
+	println!("  --max-total-image-bytes=N                 # 一次请求中所有内联输入图像的最大总字节数 (默认: 52428800)");
+	println!("  --downscale-max-dimension=PX              # 发送前把最长边超过 PX 的输入图像缩小并重新编码 (默认: 不缩放)");
+	println!("  --log-payloads=MODE                       # 上游请求和响应体日志: off、redacted (base64 替换为摘要) 或 full (默认: off)");
+	println!("  --gemini-api-key=KEY                      # 设置 Google Gemini API 密钥 (启用 gemini 后端)");
+	println!("  --openai-images-base-url=URL              # OpenAI 兼容 Images API 基础 URL (启用 openai 后端)");
+	println!("  --openai-images-api-key=KEY               # OpenAI 兼容 Images API 密钥 (可选)");
+	println!("  --fallback-models=M1,M2                   # 主模型失败时按顺序尝试的回退模型，逗号分隔");
+	println!("  --max-retries=N                           # 可重试错误的最大重试次数 (默认: 3)");
+	println!("  --requests-per-minute=N                   # 每分钟最多发送的上游请求数 (默认: 0，不限制)");
+	println!("  --max-concurrent-requests=N               # 同时进行的最大上游请求数 (默认: 4，0 表示不限制)");
+	println!("  --connect-timeout=SECS                    # 连接上游服务的超时时间 (默认: 10)");
+	println!("  --request-timeout=SECS                    # 单次上游请求的总超时时间 (默认: 180)");
+	println!("  --proxy=URL                               # HTTP(S)/SOCKS5 代理，例如 http://proxy:8080 或 socks5h://127.0.0.1:1080");
+	println!("  --local-sd-url=URL                        # 本地 Stable Diffusion 服务地址，mock 为内置模拟服务器 (启用 local 后端)");
+	println!("  --backend=NAME                            # 设置默认图像生成后端 (默认: openrouter；mock 为不访问网络的确定性后端)");
+	println!("  --supported-models=M1,M2                  # 设置允许的模型列表 (逗号分隔)");
+	println!("  --validate-models                         # 启动时通过 /models 在线校验允许的模型");
 	println!();
 	println!("API Key 设置 (选择一种方式):");
 	println!("  1. 环境变量: OPENROUTER_API_KEY=your_key");
 	println!("  2. 命令行参数: --api-key=your_key 或 --api-key your_key");
 	println!();
 	println!("环境变量:");
-	println!("  OPENROUTER_API_KEY                           # OpenRouter API 密钥 (必须)");
+	println!("  OPENROUTER_API_KEY                           # OpenRouter API 密钥 (使用 openrouter 后端时必须)");
 	println!("  MCP_MODEL                                    # 使用的模型 (默认: google/gemini-2.5-flash-image-preview:free)");
 	println!("  MCP_HTTP_PORT                                # SSE 传输时的 HTTP 端口 (默认: 6621)");
 	println!("  MCP_SAVE_DIRECTORY                           # 图片保存目录 (必须是绝对路径)");
+	println!("  MCP_INPUT_ROOTS                              # 允许读取本地输入图像的目录，系统路径分隔符分隔 (默认: 保存目录)");
+	println!("  MCP_FETCH_URLS                               # 设为 true 时由服务器下载 URL 图像输入");
+	println!("  MCP_FETCH_MAX_BYTES                          # 单张下载图像的最大字节数 (默认: 20971520)");
+	println!("  MCP_MAX_IMAGE_BYTES                          # 单张输入图像的最大字节数 (默认: 20971520)");
+	println!("  MCP_MAX_TOTAL_IMAGE_BYTES                    # 一次请求中所有内联输入图像的最大总字节数 (默认: 52428800)");
+	println!("  MCP_MAX_SOURCE_IMAGE_BYTES                   # 启用缩放时，缩放前允许读取和解码的单张源图像最大字节数 (默认: 104857600)");
+	println!("  MCP_DOWNSCALE_MAX_DIMENSION                  # 发送前输入图像最长边的像素上限，例如 2048 (默认: 0，不缩放)");
+	println!("  MCP_DOWNSCALE_FORMAT                         # 缩放后重新编码的格式: jpeg、webp (无损) 或 png (默认: jpeg)");
+	println!("  MCP_DOWNSCALE_QUALITY                        # 重新编码为 JPEG 时的质量 1-100 (默认: 85)");
+	println!("  MCP_URL_ALLOWED_DOMAINS                      # 允许的 URL 图像域名，逗号分隔 (默认: 不限制)");
+	println!("  MCP_URL_DENIED_DOMAINS                       # 拒绝的 URL 图像域名，逗号分隔");
+	println!("  RUST_LOG                                     # 日志级别，例如 debug 或 nano_banana_mcp=debug (默认: info)");
+	println!("  MCP_LOG_PAYLOADS                             # 上游请求和响应体日志: off、redacted 或 full (默认: off；密钥始终遮盖)");
+	println!("  GEMINI_API_KEY                               # Google Gemini API 密钥 (启用 gemini 后端)");
+	println!("  GEMINI_BASE_URL                              # Gemini API 基础 URL (默认: https://generativelanguage.googleapis.com/v1beta)");
+	println!("  GEMINI_MODEL                                 # gemini 后端默认模型 (默认: gemini-2.5-flash-image-preview)");
+	println!("  GEMINI_SUPPORTED_MODELS                      # gemini 后端允许的模型列表，逗号分隔");
+	println!("  OPENAI_IMAGES_BASE_URL                       # OpenAI 兼容 Images API 基础 URL (启用 openai 后端)");
+	println!("  OPENAI_IMAGES_API_KEY                        # OpenAI 兼容 Images API 密钥 (可选)");
+	println!("  OPENAI_IMAGES_MODEL                          # openai 后端默认模型 (默认: gpt-image-1)");
+	println!("  OPENAI_IMAGES_SUPPORTED_MODELS               # openai 后端允许的模型列表，逗号分隔");
+	println!("  OPENAI_IMAGES_SIZE                           # 生成图像尺寸，例如 1024x1024 (可选)");
+	println!("  MCP_FALLBACK_MODELS                          # 主模型限流、返回错误或没有图像时按顺序尝试的回退模型，逗号分隔");
+	println!("  MCP_MAX_RETRIES                              # 可重试错误 (408/429/5xx/连接失败) 的最大重试次数 (默认: 3)");
+	println!("  MCP_RETRY_BASE_DELAY_MS                      # 第一次重试前的基础退避时间，之后每次翻倍 (默认: 1000)");
+	println!("  MCP_RETRY_MAX_DELAY_MS                       # 单次等待上限，Retry-After 超过该值时不再重试 (默认: 30000)");
+	println!("  MCP_REQUESTS_PER_MINUTE                      # 每分钟最多发送的上游请求数，所有会话共享 (默认: 0，不限制)");
+	println!("  MCP_MAX_CONCURRENT_REQUESTS                  # 同时进行的最大上游请求数，所有会话共享 (默认: 4，0 表示不限制)");
+	println!("  MCP_CONNECT_TIMEOUT_SECS                     # 连接上游服务的超时时间，单位秒 (默认: 10)");
+	println!("  MCP_REQUEST_TIMEOUT_SECS                     # 单次上游请求的总超时时间，单位秒 (默认: 180)");
+	println!("  MCP_PROXY                                    # HTTP(S)/SOCKS5 代理地址 (未设置时使用 HTTPS_PROXY / ALL_PROXY)");
+	println!("  LOCAL_SD_URL                                 # 本地 Stable Diffusion 服务地址，mock 为内置模拟服务器 (启用 local 后端)");
+	println!("  LOCAL_SD_API                                 # 本地服务 API 类型: a1111 或 comfyui (默认: a1111)");
+	println!("  LOCAL_SD_MODEL                               # local 后端默认 checkpoint (默认: default，即服务端当前模型)");
+	println!("  LOCAL_SD_SUPPORTED_MODELS                    # local 后端允许的 checkpoint 列表，逗号分隔");
+	println!("  LOCAL_SD_STEPS                               # 采样步数 (默认: 20)");
+	println!("  LOCAL_SD_SIZE                                # 生成图像尺寸 (默认: 512x512)");
+	println!("  LOCAL_SD_DENOISING_STRENGTH                  # 图生图重绘幅度 (默认: 0.75)");
+	println!("  LOCAL_SD_WORKFLOW / LOCAL_SD_EDIT_WORKFLOW   # 自定义 ComfyUI 文生图 / 图生图工作流 JSON 文件");
+	println!("  MCP_BACKEND                                  # 默认图像生成后端 (默认: openrouter)");
+	println!("  MCP_SUPPORTED_MODELS                         # 允许的模型列表，逗号分隔 (默认: 两个 Gemini 图像模型)");
+	println!("  MCP_VALIDATE_MODELS                          # 设为 true 时启动时在线校验模型列表");
+	println!("  MCP_MAX_TOKENS                               # 默认最大生成 tokens 数 (默认: 1000)");
+	println!("  MCP_TEMPERATURE                              # 默认采样温度 (默认: 0.7)");
 	println!();
 	println!("示例:");
 	if is_release {
@@ -521,11 +1199,40 @@ fn print_usage() {
 	}
 }
 
+/// 以 `--flag value` 形式接收值的命令行参数，由 OpenRouterConfig 读取；解析传输方式时需要连同值一起跳过
+const VALUE_FLAGS: &[&str] = &[
+	"--api-key",
+	"--model",
+	"--backend",
+	"--supported-models",
+	"--fallback-models",
+	"--gemini-api-key",
+	"--openai-images-base-url",
+	"--openai-images-api-key",
+	"--local-sd-url",
+	"--max-retries",
+	"--requests-per-minute",
+	"--max-concurrent-requests",
+	"--connect-timeout",
+	"--request-timeout",
+	"--proxy",
+	"--input-roots",
+	"--url-allowed-domains",
+	"--url-denied-domains",
+	"--max-image-bytes",
+	"--max-total-image-bytes",
+	"--downscale-max-dimension",
+	"--log-payloads",
+];
+
 #[tokio::main]
 async fn main() -> Result<()> {
-	// 改进tracing配置，参考示例代码
+	// 日志级别由 RUST_LOG 控制，未设置时为 info；请求和响应体只在设置 MCP_LOG_PAYLOADS 时输出
 	tracing_subscriber::fmt()
-		.with_env_filter(tracing_subscriber::EnvFilter::from_default_env().add_directive(tracing::Level::DEBUG.into()))
+		.with_env_filter(
+			tracing_subscriber::EnvFilter::try_from_default_env()
+				.unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+		)
 		.with_writer(std::io::stderr)
 		.with_ansi(false)
 		.init();
@@ -537,7 +1244,7 @@ async fn main() -> Result<()> {
 		return Ok(());
 	}
 
-	// 先解析传输方式，过滤掉 --api-key、--model 和 --save-directory 相关参数
+	// 先解析传输方式，跳过带值的配置参数，并读取 --save-directory
 	let mut transport_type = "stdio"; // 默认值
 	let mut save_directory: Option<String> = None;
 	let mut i = 1;
@@ -547,20 +1254,9 @@ async fn main() -> Result<()> {
 		if arg == "stdio" || arg == "sse" {
 			transport_type = arg;
 			break;
-		} else if arg.starts_with("--api-key") || arg == "--api-key" {
-			// 跳过 --api-key 参数
-			if arg == "--api-key" && i + 1 < args.len() {
-				i += 2; // 跳过 --api-key 和它的值
-			} else {
-				i += 1; // 跳过 --api-key=value
-			}
-		} else if arg.starts_with("--model") || arg == "--model" {
-			// 跳过 --model 参数
-			if arg == "--model" && i + 1 < args.len() {
-				i += 2; // 跳过 --model 和它的值
-			} else {
-				i += 1; // 跳过 --model=value
-			}
+		} else if VALUE_FLAGS.contains(&arg.as_str()) {
+			// 跳过参数名和它的值，值由 OpenRouterConfig 读取
+			i += 2;
 		} else if arg == "--save-directory" || arg == "-s" {
 			// 处理 --save-directory 参数
 			if i + 1 < args.len() {
@@ -573,11 +1269,12 @@ async fn main() -> Result<()> {
 				std::process::exit(1);
 			}
 		} else {
+			// 开关参数和 --xxx=value 形式的参数只占一个位置
 			i += 1;
 		}
 	}
 
-	let handler = OpenRouterServer::new(save_directory)?;
+	let handler = OpenRouterServer::new(save_directory).await?;
 
 	// 使用解析出的传输方式
 	match transport_type {
@@ -641,7 +1338,7 @@ async fn main() -> Result<()> {
 				}
 			});
 
-			let cancel_token = sse_server.with_service(move || handler.clone());
+			let cancel_token = sse_server.with_service(move || handler.for_session());
 
 			
 			println!("🌐 CORS 已启用，支持跨域访问");
@@ -659,3 +1356,91 @@ async fn main() -> Result<()> {
 	
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+	use backends::mock::MOCK_MODELS;
+	use config::{InputImageConfig, PayloadLogging, RateLimitConfig, ReencodeFormat, RetryConfig, UrlFetchConfig};
+
+	/// 只注册 mock 后端、不读取环境变量和命令行参数的服务器
+	fn mock_server(save_dir: &std::path::Path) -> OpenRouterServer {
+		let config = OpenRouterConfig {
+			api_key: None,
+			base_url: "http://127.0.0.1:9".to_string(),
+			http_referer: "http://localhost:3000".to_string(),
+			x_title: "test".to_string(),
+			http_port: 6621,
+			backend: "mock".to_string(),
+			model: MOCK_MODELS[0].to_string(),
+			supported_models: Vec::new(),
+			fallback_models: Vec::new(),
+			validate_models_live: false,
+			max_tokens: 1000,
+			temperature: 0.7,
+			retry: RetryConfig { max_retries: 0, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(1) },
+			rate_limit: RateLimitConfig { requests_per_minute: 0, max_concurrent_requests: 1 },
+			http: backends::test_support::http_config(),
+			input_roots: Vec::new(),
+			url_fetch: UrlFetchConfig { fetch: false, max_bytes: 1024, allowed_domains: Vec::new(), denied_domains: Vec::new() },
+			input_images: InputImageConfig {
+				max_image_bytes: 1024 * 1024,
+				max_total_bytes: 4 * 1024 * 1024,
+				max_source_bytes: 1024 * 1024,
+				max_dimension: None,
+				format: ReencodeFormat::Png,
+				quality: 85,
+			},
+			log_payloads: PayloadLogging::Off,
+			gemini: None,
+			openai_images: None,
+			local_sd: None,
+		};
+		let backends = BackendRegistry::new(vec![std::sync::Arc::new(MockBackend::new())], "mock").unwrap();
+		OpenRouterServer::with_backends(config, backends, save_dir.to_string_lossy().into_owned())
+	}
+
+	#[tokio::test]
+	async fn mock_generation_is_saved_and_returned_inline() {
+		let save_dir = std::env::temp_dir().join(format!("nano-banana-{}-pipeline", std::process::id()));
+		let _ = std::fs::remove_dir_all(&save_dir);
+		std::fs::create_dir_all(&save_dir).unwrap();
+		let server = mock_server(&save_dir);
+
+		let backend = server.backends.get(None).unwrap();
+		let options = RequestOptions::resolve(&server.config, backend.as_ref(), None, None, None).unwrap();
+		let save = SaveTarget {
+			directory: save_dir.to_string_lossy().into_owned(),
+			base_filename: Some("generated_image".to_string()),
+			is_edit: false,
+		};
+		let run = server.run_single(
+			backend.as_ref(),
+			&options.request("一只猫".to_string(), Vec::new()),
+			ImageOperation::Generate,
+			&save,
+			&ProgressReporter::disabled(),
+		).await.unwrap();
+
+		assert!(run.content.starts_with("模拟图像"));
+		assert_eq!(run.attempts.len(), 1);
+		let saved_path = run.saved_images[0].saved_path.clone().unwrap();
+		assert_eq!(std::path::Path::new(&saved_path), save_dir.join("generated_image.png"));
+		assert_eq!(saved_image_uris(&run.saved_images), ["image://generated_image.png"]);
+		let bytes = std::fs::read(&saved_path).unwrap();
+		assert_eq!(image_utils::sniff_image_mime(&bytes), Some("image/png"));
+
+		let (summary, contents) = image_result_contents(&run.saved_images, ImageOutput::Both);
+		assert!(summary.contains(&saved_path));
+		assert_eq!(contents.len(), 1);
+		let image = contents[0].as_image().unwrap();
+		assert_eq!(image.mime_type, "image/png");
+		assert_eq!(general_purpose::STANDARD.decode(&image.data).unwrap(), bytes);
+
+		// 只返回路径时不包含内联图像
+		let (summary, contents) = image_result_contents(&run.saved_images, ImageOutput::Path);
+		assert!(summary.contains(&saved_path));
+		assert!(contents.is_empty());
+	}
+}
@@ -0,0 +1,176 @@
+use rmcp::{
+    model::{ProgressNotificationParam, ProgressToken},
+    service::{Peer, RequestContext, RoleServer},
+};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::RateLimitConfig;
+
+/// 排队期间发送进度通知的间隔
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 所有会话共享的上游请求限流器：令牌桶限制每分钟请求数，信号量限制并发请求数
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Option<Arc<Mutex<TokenBucket>>>,
+    requests_per_minute: u32,
+    semaphore: Option<Arc<Semaphore>>,
+    /// 正在等待并发槽位的请求数
+    waiting: Arc<AtomicUsize>,
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// 取走一个令牌；没有令牌时返回需要等待的时间
+    fn try_take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_second))
+        }
+    }
+}
+
+/// 持有期间占用一个并发槽位，drop 时释放
+pub struct RequestPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let bucket = (config.requests_per_minute > 0).then(|| {
+            let capacity = config.requests_per_minute as f64;
+            Arc::new(Mutex::new(TokenBucket {
+                capacity,
+                tokens: capacity,
+                refill_per_second: capacity / 60.0,
+                last_refill: Instant::now(),
+            }))
+        });
+        let semaphore = (config.max_concurrent_requests > 0)
+            .then(|| Arc::new(Semaphore::new(config.max_concurrent_requests)));
+
+        Self {
+            bucket,
+            requests_per_minute: config.requests_per_minute,
+            semaphore,
+            waiting: Arc::default(),
+        }
+    }
+
+    /// 等待并发槽位和速率令牌，排队期间向客户端发送进度通知
+    pub async fn acquire(&self, progress: &ProgressReporter) -> RequestPermit {
+        let permit = match &self.semaphore {
+            Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => self.wait_for_slot(semaphore.clone(), progress).await,
+            },
+            None => None,
+        };
+
+        // 在拿到并发槽位之后再取令牌，保证实际发送速率不超过限制
+        if let Some(bucket) = &self.bucket {
+            loop {
+                let wait = bucket.lock().unwrap().try_take();
+                let Err(wait) = wait else {
+                    break;
+                };
+                progress
+                    .report(format!(
+                        "已达到每分钟 {} 次请求的限制，约 {:.1}s 后发送",
+                        self.requests_per_minute,
+                        wait.as_secs_f64()
+                    ))
+                    .await;
+                tokio::time::sleep(wait.min(PROGRESS_INTERVAL)).await;
+            }
+        }
+
+        RequestPermit { _permit: permit }
+    }
+
+    async fn wait_for_slot(
+        &self,
+        semaphore: Arc<Semaphore>,
+        progress: &ProgressReporter,
+    ) -> Option<OwnedSemaphorePermit> {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let acquire = semaphore.acquire_owned();
+        tokio::pin!(acquire);
+        let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
+
+        let permit = loop {
+            tokio::select! {
+                permit = &mut acquire => break permit.ok(),
+                _ = ticker.tick() => {
+                    let waiting = self.waiting.load(Ordering::SeqCst);
+                    progress
+                        .report(format!("并发请求已达上限，排队中 ({} 个请求在等待)", waiting))
+                        .await;
+                }
+            }
+        };
+
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        permit
+    }
+}
+
+/// 向发起工具调用的客户端发送 MCP 进度通知；客户端没有提供 progressToken 时不发送
+pub struct ProgressReporter {
+    target: Option<(Peer<RoleServer>, ProgressToken)>,
+    progress: AtomicU32,
+}
+
+impl ProgressReporter {
+    pub fn new(context: &RequestContext<RoleServer>) -> Self {
+        Self {
+            target: context
+                .meta
+                .get_progress_token()
+                .map(|token| (context.peer.clone(), token)),
+            progress: AtomicU32::new(0),
+        }
+    }
+
+    /// 不发送任何进度通知，测试中没有请求上下文时使用
+    #[cfg(test)]
+    pub fn disabled() -> Self {
+        Self {
+            target: None,
+            progress: AtomicU32::new(0),
+        }
+    }
+
+    pub async fn report(&self, message: String) {
+        let Some((peer, token)) = &self.target else {
+            return;
+        };
+        // 进度值必须单调递增，排队期间总量未知
+        let progress = self.progress.fetch_add(1, Ordering::SeqCst) + 1;
+        let param = ProgressNotificationParam {
+            progress_token: token.clone(),
+            progress: progress as f64,
+            total: None,
+            message: Some(message),
+        };
+        if let Err(e) = peer.notify_progress(param).await {
+            tracing::warn!("发送进度通知失败: {}", e);
+        }
+    }
+}
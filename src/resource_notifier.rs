@@ -0,0 +1,65 @@
+use rmcp::{
+    model::ResourceUpdatedNotificationParam,
+    service::{Peer, RoleServer},
+};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// 单个会话订阅的资源 URI 集合
+pub type Subscriptions = Arc<Mutex<HashSet<String>>>;
+
+/// 已连接会话的注册表，用于推送资源变更通知（所有会话共享）
+#[derive(Clone, Default)]
+pub struct ResourceNotifier {
+    sessions: Arc<Mutex<Vec<Session>>>,
+}
+
+struct Session {
+    peer: Peer<RoleServer>,
+    subscriptions: Subscriptions,
+}
+
+impl ResourceNotifier {
+    /// 客户端初始化完成后登记会话
+    pub fn register(&self, peer: Peer<RoleServer>, subscriptions: Subscriptions) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|session| !session.peer.is_transport_closed());
+        sessions.push(Session { peer, subscriptions });
+    }
+
+    /// 通知所有会话资源列表已变化，并向订阅了 `updated_uris` 的会话发送更新通知
+    pub async fn notify_images_changed(&self, updated_uris: &[String]) {
+        // 先在锁内收集需要通知的会话，避免跨 await 持有锁
+        let targets: Vec<(Peer<RoleServer>, Vec<String>)> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.retain(|session| !session.peer.is_transport_closed());
+            sessions
+                .iter()
+                .map(|session| {
+                    let subscriptions = session.subscriptions.lock().unwrap();
+                    let uris = updated_uris
+                        .iter()
+                        .filter(|uri| subscriptions.contains(*uri))
+                        .cloned()
+                        .collect();
+                    (session.peer.clone(), uris)
+                })
+                .collect()
+        };
+
+        for (peer, uris) in targets {
+            if let Err(e) = peer.notify_resource_list_changed().await {
+                tracing::warn!("发送 resources/list_changed 通知失败: {}", e);
+                continue;
+            }
+            for uri in uris {
+                if let Err(e) = peer
+                    .notify_resource_updated(ResourceUpdatedNotificationParam { uri: uri.clone() })
+                    .await
+                {
+                    tracing::warn!("发送资源 {} 的更新通知失败: {}", uri, e);
+                }
+            }
+        }
+    }
+}
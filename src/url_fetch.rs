@@ -0,0 +1,348 @@
+use base64::{Engine as _, engine::general_purpose};
+use reqwest::Url;
+use rmcp::ErrorData as McpError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::backends::http_error;
+use crate::config::{HttpConfig, UrlFetchConfig};
+use crate::error::ImageError;
+use crate::image_utils;
+
+/// 下载时最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 5;
+
+/// 处理 URL 图像输入：检查域名允许/拒绝列表，启用下载时由服务器获取图像
+///
+/// 下载前解析主机地址并拒绝内网、回环和链路本地地址，连接固定到已校验的地址，
+/// 每次重定向都重新校验，防止通过图像 URL 访问服务器所在网络的内部服务 (SSRF)。
+#[derive(Clone)]
+pub struct UrlFetcher {
+    config: UrlFetchConfig,
+    http: HttpConfig,
+    /// 不做内网地址检查的主机，用于下载自托管后端返回的结果图像
+    trusted_host: Option<String>,
+}
+
+impl UrlFetcher {
+    pub fn new(config: &UrlFetchConfig, http: &HttpConfig) -> Self {
+        Self {
+            config: config.clone(),
+            http: http.clone(),
+            trusted_host: None,
+        }
+    }
+
+    /// 用于下载后端返回的结果图像：不使用输入图像的域名允许/拒绝列表，大小上限和内网地址检查不变
+    ///
+    /// `base_url` 的主机由运营者配置，自托管服务返回的本机地址允许直接下载
+    pub fn for_results(config: &UrlFetchConfig, http: &HttpConfig, base_url: &str) -> Self {
+        Self {
+            config: UrlFetchConfig {
+                allowed_domains: Vec::new(),
+                denied_domains: Vec::new(),
+                ..config.clone()
+            },
+            http: http.clone(),
+            trusted_host: Url::parse(base_url).ok()
+                .and_then(|url| url.host_str().map(|host| host.trim_end_matches('.').to_lowercase())),
+        }
+    }
+
+    /// 启用下载时返回图像的 data URL，否则在通过检查后原样返回 URL
+    pub async fn resolve(&self, input: &str) -> Result<String, McpError> {
+        let url = self.check_url(input)?;
+        if !self.config.fetch {
+            return Ok(input.to_string());
+        }
+        self.fetch(url).await
+    }
+
+    /// 检查后总是下载图像并返回 data URL，不受 `fetch` 设置影响
+    pub async fn download(&self, input: &str) -> Result<String, McpError> {
+        let url = self.check_url(input)?;
+        self.fetch(url).await
+    }
+
+    /// 检查协议和域名
+    fn check_url(&self, input: &str) -> Result<Url, ImageError> {
+        let url = Url::parse(input).map_err(|e| not_allowed(input, format!("无效的 URL: {}", e)))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(not_allowed(input, format!("不支持的协议: {}", url.scheme())));
+        }
+
+        let host = url.host_str()
+            .ok_or_else(|| not_allowed(input, "缺少主机名".to_string()))?
+            .trim_end_matches('.')
+            .to_lowercase();
+        if self.config.denied_domains.iter().any(|domain| domain_matches(&host, domain)) {
+            return Err(not_allowed(input, format!("域名 {} 在拒绝列表中", host)));
+        }
+        if !self.config.allowed_domains.is_empty()
+            && !self.config.allowed_domains.iter().any(|domain| domain_matches(&host, domain))
+        {
+            return Err(not_allowed(input, format!("域名 {} 不在允许列表中", host)));
+        }
+
+        Ok(url)
+    }
+
+    /// 下载图像并转换为 data URL，内容按魔数识别，超过大小上限时中止
+    async fn fetch(&self, mut url: Url) -> Result<String, McpError> {
+        let mut address = self.resolve_public_address(&url).await?;
+        for _ in 0..=MAX_REDIRECTS {
+            let response = self.client(&url, address)?
+                .get(url.clone())
+                .send()
+                .await
+                .map_err(|e| http_error("下载图像失败", e))?;
+
+            let status = response.status();
+            if status.is_redirection() {
+                let location = response.headers()
+                    .get(reqwest::header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .ok_or_else(|| ImageError::InvalidImage(format!("下载图像 {} 失败: 重定向缺少 Location", url)))?;
+                (url, address) = self.follow_redirect(&url, location).await?;
+                continue;
+            }
+            if !status.is_success() {
+                return Err(ImageError::InvalidImage(format!("下载图像 {} 失败，状态码: {}", url, status)).into());
+            }
+
+            let bytes = self.read_limited(response, &url).await?;
+            let mime_type = image_utils::sniff_image_mime(&bytes).ok_or_else(|| ImageError::InvalidImage(
+                format!("URL {} 的内容不是支持的图像格式 (PNG/JPEG/GIF/WebP/BMP/TIFF)", url),
+            ))?;
+            return Ok(format!("data:{};base64,{}", mime_type, general_purpose::STANDARD.encode(&bytes)));
+        }
+
+        Err(not_allowed(url.as_str(), format!("重定向次数超过 {} 次", MAX_REDIRECTS)).into())
+    }
+
+    /// 校验重定向目标的域名和解析出的地址，与初始 URL 使用相同的规则
+    async fn follow_redirect(&self, url: &Url, location: &str) -> Result<(Url, SocketAddr), ImageError> {
+        let next = url.join(location)
+            .map_err(|e| not_allowed(url.as_str(), format!("无效的重定向地址 {}: {}", location, e)))?;
+        let next = self.check_url(next.as_str())?;
+        let address = self.resolve_public_address(&next).await?;
+        Ok((next, address))
+    }
+
+    /// 解析主机地址，任一地址属于内网、回环、链路本地等受限网段时拒绝
+    async fn resolve_public_address(&self, url: &Url) -> Result<SocketAddr, ImageError> {
+        let port = url.port_or_known_default().unwrap_or(80);
+        let host = url.host_str().unwrap_or_default();
+        // IPv6 字面量在 URL 中带有方括号
+        let addresses: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| ImageError::InvalidImage(format!("解析图像 URL 的主机 {} 失败: {}", host, e)))?
+                .collect(),
+        };
+
+        let trusted = self.trusted_host.as_deref()
+            .is_some_and(|trusted| trusted == host.trim_end_matches('.').to_lowercase());
+        if let Some(address) = addresses.iter().find(|address| !trusted && is_blocked_ip(address.ip())) {
+            return Err(not_allowed(url.as_str(), format!("主机解析到受限地址 {}", address.ip())));
+        }
+        addresses.into_iter().next()
+            .ok_or_else(|| not_allowed(url.as_str(), "主机没有可用的地址".to_string()))
+    }
+
+    /// 创建固定连接到已校验地址的客户端，避免 DNS 重绑定；重定向由调用方逐跳校验
+    ///
+    /// 下载不经过代理，否则实际连接的地址由代理决定，无法校验
+    fn client(&self, url: &Url, address: SocketAddr) -> Result<reqwest::Client, McpError> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.http.connect_timeout)
+            .timeout(self.http.request_timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy();
+        if let Some(domain) = url.domain() {
+            builder = builder.resolve(domain, address);
+        }
+        builder.build().map_err(|e| http_error("创建下载客户端失败", e))
+    }
+
+    /// 读取响应体，超过大小上限时中止下载
+    async fn read_limited(&self, mut response: reqwest::Response, url: &Url) -> Result<Vec<u8>, McpError> {
+        let too_large = || McpError::from(ImageError::InvalidImage(
+            format!("图像 {} 超过下载大小上限 {} 字节（可通过 MCP_FETCH_MAX_BYTES 调整）", url, self.config.max_bytes),
+        ));
+
+        if response.content_length().is_some_and(|length| length > self.config.max_bytes) {
+            return Err(too_large());
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| http_error("下载图像失败", e))? {
+            if bytes.len() as u64 + chunk.len() as u64 > self.config.max_bytes {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+}
+
+fn not_allowed(url: &str, reason: String) -> ImageError {
+    ImageError::UrlNotAllowed { url: url.to_string(), reason }
+}
+
+/// 域名本身或其子域名都算匹配
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// 内网、回环、链路本地（含云服务元数据地址 169.254.169.254）、组播和保留地址
+fn is_blocked_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // 100.64.0.0/10 运营商级 NAT
+                || (a == 100 && (64..128).contains(&b))
+                // 198.18.0.0/15 基准测试网段
+                || (a == 198 && (b == 18 || b == 19))
+                // 240.0.0.0/4 保留网段
+                || a >= 240
+        }
+        IpAddr::V6(ip) => {
+            // 内嵌 IPv4 的地址最终会访问该 IPv4 地址，按 IPv4 规则检查
+            if let Some(ip) = embedded_ipv4(ip) {
+                return is_blocked_ip(ip.into());
+            }
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // fc00::/7 唯一本地地址
+                || (first & 0xfe00) == 0xfc00
+                // fe80::/10 链路本地地址
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// 提取 IPv6 地址中内嵌的 IPv4 地址
+///
+/// 支持 IPv4 映射 (`::ffff:a.b.c.d`)、IPv4 兼容 (`::a.b.c.d`)、NAT64 (`64:ff9b::/96`)、
+/// 6to4 (`2002::/16`，第 2、3 段) 和 Teredo (`2001::/32`，客户端地址为最后 32 位按位取反)
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let [_, _, a, b, c, d, ..] = ip.octets();
+    let last = Ipv4Addr::from(u128::from(ip) as u32);
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, _, _] | [0, 0, 0, 0, 0, 0, _, _] | [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(last),
+        [0x2002, ..] => Some(Ipv4Addr::new(a, b, c, d)),
+        [0x2001, 0, ..] => Some(Ipv4Addr::from(u32::from(last) ^ 0xffff_ffff)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::test_support::http_config;
+
+    fn blocked(ip: &str) -> bool {
+        is_blocked_ip(ip.parse().unwrap())
+    }
+
+    fn fetcher() -> UrlFetcher {
+        let config = UrlFetchConfig {
+            fetch: true,
+            max_bytes: 1024 * 1024,
+            allowed_domains: Vec::new(),
+            denied_domains: vec!["example.com".to_string()],
+        };
+        UrlFetcher::new(&config, &http_config())
+    }
+
+    #[test]
+    fn blocks_private_ipv4() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "255.255.255.255"] {
+            assert!(blocked(ip), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "1.1.1.1", "100.128.0.1"] {
+            assert!(!blocked(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn blocks_private_ipv6() {
+        for ip in ["::1", "::", "fc00::1", "fd12:3456::1", "fe80::1", "ff02::1"] {
+            assert!(blocked(ip), "{}", ip);
+        }
+        assert!(!blocked("2606:4700:4700::1111"));
+    }
+
+    #[test]
+    fn blocks_ipv6_with_embedded_private_ipv4() {
+        for ip in [
+            // IPv4 映射和 IPv4 兼容
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "::10.0.0.1",
+            // NAT64
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::192.168.0.1",
+            // 6to4：2002:7f00:0001:: 内嵌 127.0.0.1
+            "2002:7f00:1::",
+            "2002:a9fe:a9fe::1",
+            // Teredo：客户端地址按位取反，0x80ffffee 即 127.0.0.17
+            "2001:0:4136:e378:8000:63bf:80ff:ffee",
+        ] {
+            assert!(blocked(ip), "{}", ip);
+        }
+        for ip in ["::ffff:8.8.8.8", "64:ff9b::808:808", "2002:808:808::", "2001:0:4136:e378:8000:63bf:f7f7:f7f7"] {
+            assert!(!blocked(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn domain_matching_requires_label_boundary() {
+        assert!(domain_matches("example.com", "example.com"));
+        assert!(domain_matches("cdn.example.com", "example.com"));
+        assert!(!domain_matches("evil-example.com", "example.com"));
+        assert!(!domain_matches("example.com.evil.org", "example.com"));
+        assert!(!domain_matches("com", "example.com"));
+    }
+
+    #[test]
+    fn denied_domain_ignores_suffix_lookalikes() {
+        let fetcher = fetcher();
+        assert!(fetcher.check_url("https://images.example.com/cat.png").is_err());
+        assert!(fetcher.check_url("https://EXAMPLE.COM./cat.png").is_err());
+        assert!(fetcher.check_url("https://evil-example.com/cat.png").is_ok());
+        assert!(fetcher.check_url("file:///etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn redirect_to_private_address_is_rejected() {
+        let fetcher = fetcher();
+        let url = Url::parse("https://93.184.216.34/cat.png").unwrap();
+
+        for location in [
+            "http://169.254.169.254/latest/meta-data/",
+            "http://127.0.0.1:8080/admin",
+            "http://[::ffff:10.0.0.1]/",
+            "http://[64:ff9b::a9fe:a9fe]/",
+            "https://images.example.com/cat.png",
+            "file:///etc/passwd",
+        ] {
+            let error = fetcher.follow_redirect(&url, location).await.unwrap_err();
+            assert!(matches!(error, ImageError::UrlNotAllowed { .. }), "{}", location);
+        }
+        let (next, address) = fetcher.follow_redirect(&url, "/dog.png").await.unwrap();
+        assert_eq!(next.as_str(), "https://93.184.216.34/dog.png");
+        assert_eq!(address.port(), 443);
+    }
+}